@@ -0,0 +1,135 @@
+//! Compares the peak memory usage and run time of the ledger storage options on a large
+//! input. Run with `cargo bench --bench memory`. The number of rows defaults to 100 million
+//! (a file of several GB), and can be changed with the `BENCH_ROWS` environment variable.
+
+use std::{
+    env,
+    fs::File,
+    io::{BufWriter, Write},
+    process::{Command, Stdio},
+    time::Instant,
+};
+
+const BIN_PATH: &str = env!("CARGO_BIN_EXE_payments-engine");
+
+const DEFAULT_ROWS: u32 = 100_000_000;
+const CLIENTS: u32 = 10_000;
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let rows = match env::var("BENCH_ROWS") {
+        Ok(rows) => rows.parse()?,
+        Err(_) => DEFAULT_ROWS,
+    };
+
+    let temp_dir = assert_fs::TempDir::new()?;
+    let csv_path = temp_dir.path().join("transactions.csv");
+    let index_path = temp_dir.path().join("index.bin");
+    generate_transactions(&csv_path, rows)?;
+
+    println!("{} transactions, {} clients", rows, CLIENTS);
+
+    let index_path = index_path.to_string_lossy();
+    let configurations: [(&str, &[&str]); 4] = [
+        ("hashmap", &["--storage", "hashmap"]),
+        ("compact", &["--storage", "compact"]),
+        (
+            "compact, drop withdrawals",
+            &["--storage", "compact", "--drop-withdrawals"],
+        ),
+        (
+            "compact, index file",
+            &["--storage", "compact", "--index-file", &index_path],
+        ),
+    ];
+
+    for (name, args) in configurations {
+        let start = Instant::now();
+        let child = Command::new(BIN_PATH)
+            .arg(&csv_path)
+            .args(args)
+            .stdout(Stdio::null())
+            .spawn()?;
+        let peak_memory = match wait_for_peak_memory(child) {
+            Ok(peak_memory) => peak_memory,
+            // e.g. killed for running out of memory, which shouldn't stop the other runs
+            Err(err) => {
+                println!("{:<26} failed: {}", name, err);
+                continue;
+            }
+        };
+        let elapsed = start.elapsed();
+
+        println!(
+            "{:<26} time: {:>8.1?}  peak memory: {}",
+            name,
+            elapsed,
+            peak_memory.map_or_else(|| "n/a".to_string(), |kb| format!("{} MB", kb / 1024))
+        );
+    }
+
+    Ok(())
+}
+
+/// Mostly deposits and withdrawals, with the occasional dispute. Each client gets runs of ten
+/// consecutive transactions, so that withdrawals follow deposits and are accepted.
+fn generate_transactions(path: &std::path::Path, rows: u32) -> std::io::Result<()> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    writeln!(writer, "type,client,tx,amount")?;
+
+    let client = |tx: u32| (tx / 10) % CLIENTS;
+
+    for tx in 1..=rows {
+        match (tx % 100, tx % 10) {
+            (0, _) if tx > 50 => writeln!(writer, "dispute,{},{},", client(tx - 50), tx - 50)?,
+            (_, 1 | 3 | 7) => writeln!(writer, "withdrawal,{},{},0.5", client(tx), tx)?,
+            _ => writeln!(writer, "deposit,{},{},1.25", client(tx), tx)?,
+        }
+    }
+
+    writer.flush()
+}
+
+/// Waits for the child to exit, and returns its peak resident set size in KB. Fails if the
+/// child doesn't exit successfully.
+#[cfg(unix)]
+fn wait_for_peak_memory(child: std::process::Child) -> std::io::Result<Option<i64>> {
+    let mut status = 0;
+    // SAFETY: `rusage` is plain old data, and is initialised by `wait4`
+    let mut usage: libc::rusage = unsafe { std::mem::zeroed() };
+    let pid = child.id() as libc::pid_t;
+
+    if unsafe { libc::wait4(pid, &mut status, 0, &mut usage) } < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+
+    if libc::WIFSIGNALED(status) {
+        return Err(std::io::Error::other(format!(
+            "killed by signal {}",
+            libc::WTERMSIG(status)
+        )));
+    }
+    if libc::WEXITSTATUS(status) != 0 {
+        return Err(std::io::Error::other(format!(
+            "exited with status {}",
+            libc::WEXITSTATUS(status)
+        )));
+    }
+
+    // Linux and the BSDs report the peak in KB, but macOS reports it in bytes
+    let peak_memory = if cfg!(any(target_os = "macos", target_os = "ios")) {
+        usage.ru_maxrss / 1024
+    } else {
+        usage.ru_maxrss
+    };
+
+    Ok(Some(peak_memory))
+}
+
+#[cfg(not(unix))]
+fn wait_for_peak_memory(mut child: std::process::Child) -> std::io::Result<Option<i64>> {
+    let status = child.wait()?;
+    if !status.success() {
+        return Err(std::io::Error::other(format!("exited with {}", status)));
+    }
+    Ok(None)
+}
@@ -0,0 +1,76 @@
+//! Compares the throughput of processing transactions on a single thread against sharding
+//! accounts between several worker threads. Run with `cargo bench`.
+
+use std::{
+    fmt::Write as _,
+    process::{Command, Stdio},
+    time::{Duration, Instant},
+};
+
+use assert_fs::prelude::*;
+
+const BIN_PATH: &str = env!("CARGO_BIN_EXE_payments-engine");
+
+const TRANSACTIONS: u32 = 1_000_000;
+const CLIENTS: u32 = 10_000;
+const RUNS: u32 = 3;
+const SHARDS: [usize; 4] = [1, 2, 4, 8];
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let csv_file = assert_fs::NamedTempFile::new("transactions.csv")?;
+    csv_file.write_str(&generate_transactions())?;
+
+    println!("{} transactions, {} clients", TRANSACTIONS, CLIENTS);
+
+    for shards in SHARDS {
+        let mut total = Duration::ZERO;
+
+        for _ in 0..RUNS {
+            let start = Instant::now();
+            let status = Command::new(BIN_PATH)
+                .arg(csv_file.path())
+                .arg("--shards")
+                .arg(shards.to_string())
+                .stdout(Stdio::null())
+                .status()?;
+            total += start.elapsed();
+
+            assert!(status.success());
+        }
+
+        let average = total / RUNS;
+        println!(
+            "shards: {:>2}  average: {:>8.1?}  throughput: {:>10.0} tx/s",
+            shards,
+            average,
+            f64::from(TRANSACTIONS) / average.as_secs_f64()
+        );
+    }
+
+    Ok(())
+}
+
+/// Mostly deposits and withdrawals, with the occasional dispute which is later resolved
+fn generate_transactions() -> String {
+    let mut csv = String::from("type,client,tx,amount\n");
+
+    for tx in 1..=TRANSACTIONS {
+        // Every tenth transaction disputes an earlier deposit, and the dispute is resolved
+        // five transactions later
+        let disputed_tx = match tx % 10 {
+            0 => tx.checked_sub(CLIENTS * 2 - 2),
+            5 => tx.checked_sub(CLIENTS * 2 + 3),
+            _ => None,
+        };
+
+        let line = match (tx % 10, disputed_tx) {
+            (0, Some(disputed_tx)) => format!("dispute,{},{},", disputed_tx % CLIENTS, disputed_tx),
+            (5, Some(disputed_tx)) => format!("resolve,{},{},", disputed_tx % CLIENTS, disputed_tx),
+            (1 | 3, _) => format!("withdrawal,{},{},0.5", tx % CLIENTS, tx),
+            _ => format!("deposit,{},{},1.25", tx % CLIENTS, tx),
+        };
+        writeln!(csv, "{}", line).unwrap();
+    }
+
+    csv
+}
@@ -0,0 +1,91 @@
+use std::{fmt, io};
+
+use clap::ValueEnum;
+
+use crate::amounts::OutputScale;
+use crate::csv_accounts;
+use crate::json_accounts;
+use crate::ledger::Account;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, ValueEnum)]
+pub(crate) enum Format {
+    /// CSV with a header row
+    #[default]
+    Csv,
+    /// A single JSON array of accounts
+    Json,
+    /// JSON Lines, with one account per line
+    Jsonl,
+}
+
+/// Order in which accounts are written. Accounts which compare equal are always ordered by
+/// client id, so the output is stable between runs regardless of storage backend.
+#[derive(Clone, Copy, Debug, Default, PartialEq, ValueEnum)]
+pub(crate) enum SortKey {
+    /// Ascending client id
+    #[default]
+    Client,
+    /// Ascending total funds
+    Total,
+    /// Ascending available funds
+    Available,
+}
+
+impl SortKey {
+    pub fn sort(&self, accounts: &mut [&Account]) {
+        match self {
+            SortKey::Client => accounts.sort_by_key(|account| account.client_id),
+            SortKey::Total => accounts.sort_by_key(|account| (account.total(), account.client_id)),
+            SortKey::Available => {
+                accounts.sort_by_key(|account| (account.available, account.client_id))
+            }
+        }
+    }
+}
+
+/// Writes the final account balances in any of the supported output [`Format`]s
+pub(crate) enum Writer<W> {
+    Csv(csv_accounts::Writer<W>),
+    Json(json_accounts::Writer<W>),
+}
+
+impl<W: io::Write> Writer<W> {
+    pub fn from_writer(writer: W, format: Format, scale: OutputScale) -> Writer<W> {
+        match format {
+            Format::Csv => Writer::Csv(csv_accounts::Writer::from_writer(writer).with_scale(scale)),
+            Format::Json => {
+                Writer::Json(json_accounts::Writer::from_writer(writer).with_scale(scale))
+            }
+            Format::Jsonl => {
+                Writer::Json(json_accounts::Writer::from_writer_lines(writer).with_scale(scale))
+            }
+        }
+    }
+
+    pub fn write_all<'a, I: Iterator<Item = &'a Account>>(
+        self,
+        accounts_iterator: I,
+    ) -> Result<(), Error> {
+        match self {
+            Writer::Csv(writer) => writer.write_all(accounts_iterator).map_err(Error::Csv),
+            Writer::Json(writer) => writer.write_all(accounts_iterator).map_err(Error::Json),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum Error {
+    Csv(csv::Error),
+    Json(serde_json::Error),
+}
+
+impl std::error::Error for Error {}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Csv(err) => write!(f, "Failed to write accounts CSV: {}", err),
+            Self::Json(err) => write!(f, "Failed to write accounts JSON: {}", err),
+        }
+    }
+}
@@ -0,0 +1,108 @@
+use clap::ValueEnum;
+use rust_decimal::{Decimal, RoundingStrategy};
+
+/// How amounts are rounded when they have more decimal places than allowed
+#[derive(Clone, Copy, Debug, Default, PartialEq, ValueEnum)]
+pub(crate) enum Rounding {
+    /// Round to the nearest value, with midpoints rounded to the nearest even digit
+    #[default]
+    HalfEven,
+    /// Round to the nearest value, with midpoints rounded away from zero
+    HalfUp,
+    /// Round to the nearest value, with midpoints rounded towards zero
+    HalfDown,
+    /// Always round towards zero
+    TowardZero,
+    /// Always round away from zero
+    AwayFromZero,
+    /// Always round towards negative infinity
+    Floor,
+    /// Always round towards positive infinity
+    Ceiling,
+}
+
+impl From<Rounding> for RoundingStrategy {
+    fn from(rounding: Rounding) -> Self {
+        match rounding {
+            Rounding::HalfEven => RoundingStrategy::MidpointNearestEven,
+            Rounding::HalfUp => RoundingStrategy::MidpointAwayFromZero,
+            Rounding::HalfDown => RoundingStrategy::MidpointTowardZero,
+            Rounding::TowardZero => RoundingStrategy::ToZero,
+            Rounding::AwayFromZero => RoundingStrategy::AwayFromZero,
+            Rounding::Floor => RoundingStrategy::ToNegativeInfinity,
+            Rounding::Ceiling => RoundingStrategy::ToPositiveInfinity,
+        }
+    }
+}
+
+/// What to do with input amounts which have more decimal places than allowed
+#[derive(Clone, Copy, Debug, Default, PartialEq, ValueEnum)]
+pub(crate) enum ExcessPrecision {
+    /// Reject the transaction as invalid
+    #[default]
+    Reject,
+    /// Round the amount to the allowed number of decimal places
+    Round,
+}
+
+/// Limits the number of decimal places of amounts read from the input
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct InputPrecision {
+    pub decimal_places: u32,
+    pub excess: ExcessPrecision,
+    pub rounding: Rounding,
+}
+
+impl Default for InputPrecision {
+    fn default() -> Self {
+        InputPrecision {
+            decimal_places: 4,
+            excess: ExcessPrecision::default(),
+            rounding: Rounding::default(),
+        }
+    }
+}
+
+impl InputPrecision {
+    /// Returns the amount without any trailing zeros, or `None` if it has too many decimal
+    /// places and excess precision is rejected. Trailing zeros don't count towards the number
+    /// of decimal places, so e.g. `1.50000` is always accepted.
+    pub fn apply(&self, amount: Decimal) -> Option<Decimal> {
+        let amount = amount.normalize();
+        if amount.scale() <= self.decimal_places {
+            return Some(amount);
+        }
+
+        match self.excess {
+            ExcessPrecision::Reject => None,
+            ExcessPrecision::Round => Some(
+                amount
+                    .round_dp_with_strategy(self.decimal_places, self.rounding.into())
+                    .normalize(),
+            ),
+        }
+    }
+}
+
+/// Formatting of amounts written to the output
+#[derive(Clone, Copy, Debug, Default)]
+pub(crate) struct OutputScale {
+    /// Fixed number of decimal places to write. If not given, amounts are written without
+    /// trailing zeros.
+    pub decimal_places: Option<u32>,
+    pub rounding: Rounding,
+}
+
+impl OutputScale {
+    pub fn apply(&self, amount: Decimal) -> Decimal {
+        match self.decimal_places {
+            Some(decimal_places) => {
+                let mut amount =
+                    amount.round_dp_with_strategy(decimal_places, self.rounding.into());
+                amount.rescale(decimal_places);
+                amount
+            }
+            None => amount.normalize(),
+        }
+    }
+}
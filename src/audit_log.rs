@@ -0,0 +1,226 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::Path,
+};
+
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    json_lines,
+    ledger::{Account, Transaction},
+};
+
+/// Append-only log of every change made to an account, written as JSON Lines. Each event
+/// gives the changes to the account's balances along with the resulting balances, so that
+/// the final balance of every account can be reconstructed, and explained, from the log
+/// alone.
+///
+/// Events are only written for transactions which are applied, since refused and replayed
+/// transactions never change an account.
+pub(crate) struct AuditLog {
+    log: BufWriter<File>,
+    /// Sequence number of the last event written to the log
+    sequence: u64,
+    /// Each client's account as recorded by its last event, so that the log can be checked
+    /// against the ledger
+    accounts_by_client_id: HashMap<u16, Account>,
+}
+
+/// A single change to an account, caused by applying a transaction. Amounts are written
+/// without trailing zeros.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub(crate) struct AuditEvent {
+    /// Position of the event in the log, starting from 1
+    pub seq: u64,
+    /// Type of the transaction which caused the event
+    pub event: String,
+    pub client: u16,
+    pub tx: u32,
+    pub dispute: Option<u32>,
+    /// Reason code given for an administrative transaction
+    pub reason_code: Option<String>,
+    pub available_delta: Decimal,
+    pub held_delta: Decimal,
+    pub available: Decimal,
+    pub held: Decimal,
+    pub total: Decimal,
+    pub locked: bool,
+    pub closed: bool,
+}
+
+impl AuditLog {
+    /// Opens the log at `path`, creating it if it doesn't exist. New events are appended
+    /// after any existing events, continuing their sequence numbers. An incomplete event at
+    /// the end of the log, e.g. because the program was killed while writing it, is
+    /// discarded.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<AuditLog, Error> {
+        let mut sequence = 0;
+        let mut accounts_by_client_id = HashMap::new();
+
+        let file = json_lines::open_log(path, |_, event: AuditEvent| -> Result<(), Error> {
+            sequence = event.seq;
+            accounts_by_client_id.insert(event.client, event.account());
+            Ok(())
+        })?;
+
+        Ok(AuditLog {
+            log: BufWriter::new(file),
+            sequence,
+            accounts_by_client_id,
+        })
+    }
+
+    /// Compares the accounts with the balances recorded by each client's last event, and
+    /// returns the lowest client id which doesn't match, if any. Clients with events but no
+    /// account, or with an account but no events, don't match.
+    pub fn find_mismatch<'a, I: IntoIterator<Item = &'a Account>>(
+        &self,
+        accounts: I,
+    ) -> Option<u16> {
+        let mut client_ids = HashSet::new();
+        let mut mismatches = Vec::new();
+
+        for account in accounts {
+            client_ids.insert(account.client_id);
+
+            let matches = self
+                .accounts_by_client_id
+                .get(&account.client_id)
+                .is_some_and(|recorded| {
+                    recorded.available == account.available
+                        && recorded.held == account.held
+                        && recorded.is_locked == account.is_locked
+                        && recorded.is_closed == account.is_closed
+                });
+            if !matches {
+                mismatches.push(account.client_id);
+            }
+        }
+
+        mismatches.extend(
+            self.accounts_by_client_id
+                .keys()
+                .filter(|client_id| !client_ids.contains(*client_id)),
+        );
+        mismatches.into_iter().min()
+    }
+
+    /// Appends an event for an applied transaction, given the client's account before the
+    /// transaction (if it had one) and after it. The event is written to the file
+    /// immediately.
+    pub fn record(
+        &mut self,
+        transaction: &TransactionDetails,
+        before: Option<&Account>,
+        after: &Account,
+    ) -> Result<(), Error> {
+        let (available_before, held_before) = before
+            .map(|account| (account.available, account.held))
+            .unwrap_or_default();
+
+        let event = AuditEvent {
+            seq: self.sequence + 1,
+            event: transaction.tx_type.to_string(),
+            client: after.client_id,
+            tx: transaction.tx_id,
+            dispute: transaction.dispute_id,
+            reason_code: transaction.reason_code.clone(),
+            available_delta: (after.available - available_before).normalize(),
+            held_delta: (after.held - held_before).normalize(),
+            available: after.available.normalize(),
+            held: after.held.normalize(),
+            total: after.total().normalize(),
+            locked: after.is_locked,
+            closed: after.is_closed,
+        };
+
+        serde_json::to_writer(&mut self.log, &event).map_err(|err| Error::Io(err.into()))?;
+        self.log.write_all(b"\n").map_err(Error::Io)?;
+        self.log.flush().map_err(Error::Io)?;
+
+        self.sequence = event.seq;
+        self.accounts_by_client_id
+            .insert(event.client, event.account());
+        Ok(())
+    }
+
+    /// Waits for the log to be written to disk
+    pub fn sync(&mut self) -> Result<(), Error> {
+        self.log.flush().map_err(Error::Io)?;
+        self.log.get_ref().sync_data().map_err(Error::Io)
+    }
+}
+
+impl AuditEvent {
+    /// The client's account after the event
+    fn account(&self) -> Account {
+        Account {
+            client_id: self.client,
+            available: self.available,
+            held: self.held,
+            is_locked: self.locked,
+            is_closed: self.closed,
+        }
+    }
+}
+
+/// Details of a transaction which are included in its event, taken before the transaction
+/// is handed to the ledger
+#[derive(Clone, Debug)]
+pub(crate) struct TransactionDetails {
+    tx_type: &'static str,
+    tx_id: u32,
+    dispute_id: Option<u32>,
+    reason_code: Option<String>,
+}
+
+impl TransactionDetails {
+    pub fn new(transaction: &Transaction) -> TransactionDetails {
+        let (tx_id, dispute_id, reason_code) = match transaction {
+            Transaction::Standard(transaction) => (transaction.tx_id, None, None),
+            Transaction::Dispute(transaction) => (transaction.tx_id, transaction.dispute_id, None),
+            Transaction::Admin(transaction) => {
+                (transaction.tx_id, None, transaction.reason.clone())
+            }
+        };
+
+        TransactionDetails {
+            tx_type: transaction.type_name(),
+            tx_id,
+            dispute_id,
+            reason_code,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    Corrupt { line: u64, err: serde_json::Error },
+}
+
+impl From<json_lines::Error> for Error {
+    fn from(err: json_lines::Error) -> Error {
+        match err {
+            json_lines::Error::Io(err) => Error::Io(err),
+            json_lines::Error::Corrupt { line, err } => Error::Corrupt { line, err },
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(err) => write!(f, "Failed to access audit log: {}", err),
+            Self::Corrupt { line, err } => {
+                write!(f, "Audit log is corrupt at line {}: {}", line, err)
+            }
+        }
+    }
+}
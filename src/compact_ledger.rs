@@ -0,0 +1,300 @@
+use std::{
+    cell::RefCell,
+    collections::{hash_map::Values, HashMap},
+    fs::{File, OpenOptions},
+    io::{self, Read, Seek, SeekFrom, Write},
+    path::Path,
+};
+
+use rust_decimal::Decimal;
+
+use crate::ledger::{
+    Account, AdminTransaction, Dispute, LedgerStorage, StandardTransaction, StandardTransactionType,
+};
+
+/// Storage for a ledger which minimises the memory used per transaction, for inputs which are
+/// too large for [`crate::hashmap_ledger::HashMapLedger`]. Transactions are stored in a
+/// compact index of [`PackedTransaction`]s, which can be kept either in memory or in a file.
+/// Disputes and administrative transactions are stored separately, because they are only a
+/// small proportion of all transactions.
+///
+/// [`LedgerStorage`] can't report errors, so the first error accessing the index file is kept
+/// and returned by [`CompactLedger::check`], which should be called after each transaction.
+pub(crate) struct CompactLedger {
+    accounts_by_client_id: HashMap<u16, Account>,
+    index: Index,
+    disputes_by_tx_id: HashMap<u32, Vec<Dispute>>,
+    admin_transactions_by_id: HashMap<u32, AdminTransaction>,
+    drop_withdrawals: bool,
+    error: RefCell<Option<io::Error>>,
+}
+
+impl CompactLedger {
+    /// Creates a ledger which keeps the transaction index in memory
+    pub fn new() -> CompactLedger {
+        CompactLedger::with_index(Index::Memory {
+            transactions_by_id: HashMap::new(),
+            dropped_tx_ids: Vec::new(),
+        })
+    }
+
+    /// Creates a ledger which keeps the transaction index in the file at `path`, which is
+    /// created or truncated. The file is only used as scratch space, and can't be reused
+    /// between runs.
+    pub fn with_index_file<P: AsRef<Path>>(path: P) -> io::Result<CompactLedger> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)?;
+
+        Ok(CompactLedger::with_index(Index::File(file)))
+    }
+
+    fn with_index(index: Index) -> CompactLedger {
+        CompactLedger {
+            accounts_by_client_id: HashMap::new(),
+            index,
+            disputes_by_tx_id: HashMap::new(),
+            admin_transactions_by_id: HashMap::new(),
+            drop_withdrawals: false,
+            error: RefCell::new(None),
+        }
+    }
+
+    /// Only keep the ids of withdrawals rather than the withdrawals themselves, so that
+    /// duplicate ids are still detected. Disputes against dropped withdrawals are refused as
+    /// unknown transactions, and a re-delivered withdrawal can't be told apart from a
+    /// conflicting one, so is refused as a duplicate. This should only be used when
+    /// withdrawals are never disputed.
+    pub fn with_dropped_withdrawals(mut self) -> CompactLedger {
+        self.drop_withdrawals = true;
+        self
+    }
+
+    /// Returns the first error that occurred while accessing the index file since the last
+    /// check
+    pub fn check(&mut self) -> io::Result<()> {
+        match self.error.get_mut().take() {
+            Some(err) => Err(err),
+            None => Ok(()),
+        }
+    }
+
+    fn get_entry(&self, tx_id: u32) -> Option<Entry> {
+        self.index.get(tx_id).unwrap_or_else(|err| {
+            self.error.borrow_mut().get_or_insert(err);
+            None
+        })
+    }
+}
+
+impl<'a> LedgerStorage<'a> for CompactLedger {
+    type AccountsIterator = Values<'a, u16, Account>;
+
+    fn get_accounts(&'a self) -> Self::AccountsIterator {
+        self.accounts_by_client_id.values()
+    }
+
+    fn get_account(&self, client_id: u16) -> Option<Account> {
+        self.accounts_by_client_id.get(&client_id).cloned()
+    }
+
+    fn put_account(&mut self, account: Account) {
+        self.accounts_by_client_id
+            .insert(account.client_id, account);
+    }
+
+    fn get_transaction(&self, tx_id: u32) -> Option<StandardTransaction> {
+        match self.get_entry(tx_id)? {
+            Entry::Transaction(transaction) => Some(StandardTransaction {
+                tx_type: transaction.tx_type,
+                client_id: transaction.client_id,
+                tx_id,
+                amount: transaction.amount,
+                disputes: self
+                    .disputes_by_tx_id
+                    .get(&tx_id)
+                    .cloned()
+                    .unwrap_or_default(),
+            }),
+            Entry::DroppedWithdrawal => None,
+        }
+    }
+
+    fn contains_transaction(&self, tx_id: u32) -> bool {
+        self.get_entry(tx_id).is_some()
+    }
+
+    fn put_transaction(&mut self, transaction: StandardTransaction) {
+        let tx_id = transaction.tx_id;
+
+        let entry = if self.drop_withdrawals
+            && transaction.tx_type == StandardTransactionType::Withdrawal
+        {
+            Entry::DroppedWithdrawal
+        } else {
+            Entry::Transaction(PackedTransaction {
+                client_id: transaction.client_id,
+                tx_type: transaction.tx_type,
+                amount: transaction.amount,
+            })
+        };
+
+        if transaction.disputes.is_empty() {
+            self.disputes_by_tx_id.remove(&tx_id);
+        } else {
+            self.disputes_by_tx_id.insert(tx_id, transaction.disputes);
+        }
+
+        if let Err(err) = self.index.put(tx_id, entry) {
+            self.error.get_mut().get_or_insert(err);
+        }
+    }
+
+    fn get_admin_transaction(&self, tx_id: u32) -> Option<AdminTransaction> {
+        self.admin_transactions_by_id.get(&tx_id).cloned()
+    }
+
+    fn put_admin_transaction(&mut self, transaction: AdminTransaction) {
+        self.admin_transactions_by_id
+            .insert(transaction.tx_id, transaction);
+    }
+}
+
+/// A deposit or withdrawal without its id, which is the key in the index, or its disputes.
+/// This is 20 bytes, compared to 56 bytes for a [`StandardTransaction`].
+#[derive(Clone, Copy, Debug)]
+struct PackedTransaction {
+    client_id: u16,
+    tx_type: StandardTransactionType,
+    amount: Decimal,
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Entry {
+    Transaction(PackedTransaction),
+    DroppedWithdrawal,
+}
+
+/// Size of each entry in an index file
+const ENTRY_SIZE: u64 = 20;
+
+// Tags identifying the kind of entry in an index file. Files are created empty (i.e. zeroed),
+// so a zero tag means there is no entry.
+const TAG_EMPTY: u8 = 0;
+const TAG_DEPOSIT: u8 = 1;
+const TAG_WITHDRAWAL: u8 = 2;
+const TAG_DROPPED_WITHDRAWAL: u8 = 3;
+
+enum Index {
+    Memory {
+        transactions_by_id: HashMap<u32, PackedTransaction>,
+        /// Bitset of dropped withdrawal ids, which only needs one bit per id up to the highest
+        /// dropped id
+        dropped_tx_ids: Vec<u64>,
+    },
+    /// Entries are stored at the offset given by their tx id, so no separate lookup table
+    /// is needed. Space is only allocated for the ranges of ids which are actually used on
+    /// filesystems which support sparse files.
+    File(File),
+}
+
+impl Index {
+    fn get(&self, tx_id: u32) -> io::Result<Option<Entry>> {
+        match self {
+            Index::Memory {
+                transactions_by_id,
+                dropped_tx_ids,
+            } => Ok(match transactions_by_id.get(&tx_id) {
+                Some(transaction) => Some(Entry::Transaction(*transaction)),
+                None if bitset_contains(dropped_tx_ids, tx_id) => Some(Entry::DroppedWithdrawal),
+                None => None,
+            }),
+            Index::File(file) => {
+                // `&File` can be read from, so a shared reference is enough
+                let mut file = file;
+                let mut bytes = [0; ENTRY_SIZE as usize];
+                file.seek(SeekFrom::Start(u64::from(tx_id) * ENTRY_SIZE))?;
+
+                // Reading beyond the end of the file means the entry has never been written
+                match file.read_exact(&mut bytes) {
+                    Ok(()) => Ok(decode_entry(&bytes)),
+                    Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => Ok(None),
+                    Err(err) => Err(err),
+                }
+            }
+        }
+    }
+
+    fn put(&mut self, tx_id: u32, entry: Entry) -> io::Result<()> {
+        match self {
+            Index::Memory {
+                transactions_by_id,
+                dropped_tx_ids,
+            } => {
+                match entry {
+                    Entry::Transaction(transaction) => {
+                        transactions_by_id.insert(tx_id, transaction);
+                    }
+                    Entry::DroppedWithdrawal => bitset_insert(dropped_tx_ids, tx_id),
+                }
+                Ok(())
+            }
+            Index::File(file) => {
+                file.seek(SeekFrom::Start(u64::from(tx_id) * ENTRY_SIZE))?;
+                file.write_all(&encode_entry(&entry))
+            }
+        }
+    }
+}
+
+fn bitset_contains(bits: &[u64], tx_id: u32) -> bool {
+    bits.get(tx_id as usize / 64)
+        .is_some_and(|word| word & (1 << (tx_id % 64)) != 0)
+}
+
+fn bitset_insert(bits: &mut Vec<u64>, tx_id: u32) {
+    let word = tx_id as usize / 64;
+    if word >= bits.len() {
+        bits.resize(word + 1, 0);
+    }
+    bits[word] |= 1 << (tx_id % 64);
+}
+
+fn encode_entry(entry: &Entry) -> [u8; ENTRY_SIZE as usize] {
+    let mut bytes = [0; ENTRY_SIZE as usize];
+
+    match entry {
+        Entry::Transaction(transaction) => {
+            bytes[0] = match transaction.tx_type {
+                StandardTransactionType::Deposit => TAG_DEPOSIT,
+                StandardTransactionType::Withdrawal => TAG_WITHDRAWAL,
+            };
+            bytes[2..4].copy_from_slice(&transaction.client_id.to_le_bytes());
+            bytes[4..].copy_from_slice(&transaction.amount.serialize());
+        }
+        Entry::DroppedWithdrawal => bytes[0] = TAG_DROPPED_WITHDRAWAL,
+    }
+
+    bytes
+}
+
+fn decode_entry(bytes: &[u8; ENTRY_SIZE as usize]) -> Option<Entry> {
+    let tx_type = match bytes[0] {
+        TAG_EMPTY => return None,
+        TAG_DROPPED_WITHDRAWAL => return Some(Entry::DroppedWithdrawal),
+        TAG_DEPOSIT => StandardTransactionType::Deposit,
+        _ => StandardTransactionType::Withdrawal,
+    };
+
+    let mut amount = [0; 16];
+    amount.copy_from_slice(&bytes[4..]);
+
+    Some(Entry::Transaction(PackedTransaction {
+        client_id: u16::from_le_bytes([bytes[2], bytes[3]]),
+        tx_type,
+        amount: Decimal::deserialize(amount),
+    }))
+}
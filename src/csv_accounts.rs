@@ -1,10 +1,25 @@
+use rust_decimal::Decimal;
+use serde::Deserialize;
+
+use crate::amounts::OutputScale;
 use crate::ledger::Account;
 
-pub(crate) struct Writer<W>(W);
+pub(crate) struct Writer<W> {
+    writer: W,
+    scale: OutputScale,
+}
 
 impl<W> Writer<W> {
     pub fn from_writer(writer: W) -> Writer<W> {
-        Writer(writer)
+        Writer {
+            writer,
+            scale: OutputScale::default(),
+        }
+    }
+
+    pub fn with_scale(mut self, scale: OutputScale) -> Writer<W> {
+        self.scale = scale;
+        self
     }
 }
 
@@ -13,30 +28,49 @@ impl<W: std::io::Write> Writer<W> {
         self,
         accounts_iterator: I,
     ) -> Result<(), csv::Error> {
-        let mut writer = csv::Writer::from_writer(self.0);
+        let mut writer = csv::Writer::from_writer(self.writer);
 
-        writer.write_record(&["client", "available", "held", "total", "locked"])?;
+        writer.write_record(["client", "available", "held", "total", "locked"])?;
 
         for account in accounts_iterator {
-            match account {
-                Account {
-                    client_id,
-                    available,
-                    held,
-                    is_locked,
-                } => {
-                    let fields = [
-                        client_id.to_string(),
-                        available.to_string(),
-                        held.to_string(),
-                        account.total().to_string(),
-                        is_locked.to_string(),
-                    ];
-                    writer.write_record(fields)?;
-                }
-            };
+            let Account {
+                client_id,
+                available,
+                held,
+                is_locked,
+                // Closed accounts are always locked, so closure isn't reported separately
+                is_closed: _,
+            } = account;
+
+            let fields = [
+                client_id.to_string(),
+                self.scale.apply(*available).to_string(),
+                self.scale.apply(*held).to_string(),
+                self.scale.apply(account.total()).to_string(),
+                is_locked.to_string(),
+            ];
+            writer.write_record(fields)?;
         }
 
         Ok(())
     }
 }
+
+/// An account as written by [`Writer`]
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub(crate) struct AccountRow {
+    pub client: u16,
+    pub available: Decimal,
+    pub held: Decimal,
+    pub total: Decimal,
+    pub locked: bool,
+}
+
+/// Reads every account from CSV written by [`Writer`]
+pub(crate) fn read_all<R: std::io::Read>(reader: R) -> Result<Vec<AccountRow>, csv::Error> {
+    csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_reader(reader)
+        .deserialize()
+        .collect()
+}
@@ -1,70 +1,232 @@
-use std::fs::File;
-use std::{fmt, path::Path};
+use std::{collections::HashMap, fmt, io, str::FromStr};
 
-use csv::{DeserializeRecordsIter, ReaderBuilder};
+use csv::{ReaderBuilder, StringRecord};
 use rust_decimal::Decimal;
 use serde::Deserialize;
 
+use crate::amounts::InputPrecision;
 use crate::ledger::{
-    DisputeTransaction, DisputeTransactionType, StandardTransaction, StandardTransactionType,
-    Transaction,
+    AdminTransaction, AdminTransactionType, DisputeTransaction, DisputeTransactionType,
+    StandardTransaction, StandardTransactionType, Transaction,
 };
 
-pub(crate) struct Reader(csv::Reader<File>);
+pub(crate) struct Reader<R> {
+    reader: csv::Reader<R>,
+    type_aliases: TypeAliases,
+    precision: InputPrecision,
+}
 
-impl Reader {
-    pub fn from_path<P: AsRef<Path>>(path: P) -> Result<Reader, Error> {
+impl<R: io::Read> Reader<R> {
+    pub fn from_reader(reader: R) -> Reader<R> {
         let underlying_reader = ReaderBuilder::new()
             .trim(csv::Trim::All)
-            .from_path(path)
-            .map_err(|err| Error::Csv(err))?;
+            .from_reader(reader);
+
+        Reader {
+            reader: underlying_reader,
+            type_aliases: TypeAliases::default(),
+            precision: InputPrecision::default(),
+        }
+    }
+
+    /// Accept the given aliases in addition to the standard transaction type names
+    pub fn with_type_aliases(mut self, type_aliases: TypeAliases) -> Reader<R> {
+        self.type_aliases = type_aliases;
+        self
+    }
+
+    /// Limit the number of decimal places of amounts
+    pub fn with_precision(mut self, precision: InputPrecision) -> Reader<R> {
+        self.precision = precision;
+        self
+    }
+
+    pub fn iter(&mut self) -> Result<CsvTransactionIterator<'_, R>, Error> {
+        let headers = self.reader.headers().map_err(Error::Csv)?.clone();
+        let columns = Columns::from_headers(&headers);
+
+        Ok(CsvTransactionIterator {
+            reader: &mut self.reader,
+            type_aliases: &self.type_aliases,
+            precision: self.precision,
+            headers,
+            columns,
+        })
+    }
+}
+
+/// A single row read from the transactions input
+pub(crate) struct TransactionRow {
+    /// Line number of the row within the input
+    pub line: u64,
+    pub fields: RawFields,
+    pub transaction: Result<Transaction, Error>,
+}
+
+/// Fields of a row exactly as they appeared in the input (after trimming), so that
+/// rows can be reported back even if they couldn't be parsed.
+#[derive(Debug, Default)]
+pub(crate) struct RawFields {
+    pub tx_type: String,
+    pub client: String,
+    pub tx: String,
+    pub amount: String,
+}
 
-        Ok(Reader(underlying_reader))
+/// Positions of the known columns within each row, based on the header row
+struct Columns {
+    tx_type: Option<usize>,
+    client: Option<usize>,
+    tx: Option<usize>,
+    amount: Option<usize>,
+}
+
+impl Columns {
+    fn from_headers(headers: &StringRecord) -> Columns {
+        let position = |name: &str| headers.iter().position(|header| header == name);
+
+        Columns {
+            tx_type: position("type"),
+            client: position("client"),
+            tx: position("tx"),
+            amount: position("amount"),
+        }
     }
 
-    pub fn iter<'a>(&mut self) -> CsvTransactionIterator {
-        CsvTransactionIterator(self.0.deserialize())
+    fn raw_fields(&self, record: &StringRecord) -> RawFields {
+        let field = |position: Option<usize>| {
+            position
+                .and_then(|i| record.get(i))
+                .unwrap_or_default()
+                .to_string()
+        };
+
+        RawFields {
+            tx_type: field(self.tx_type),
+            client: field(self.client),
+            tx: field(self.tx),
+            amount: field(self.amount),
+        }
     }
 }
 
-pub(crate) struct CsvTransactionIterator<'r>(DeserializeRecordsIter<'r, File, TransactionRecord>);
+pub(crate) struct CsvTransactionIterator<'r, R> {
+    reader: &'r mut csv::Reader<R>,
+    type_aliases: &'r TypeAliases,
+    precision: InputPrecision,
+    headers: StringRecord,
+    columns: Columns,
+}
 
-impl<'a> Iterator for CsvTransactionIterator<'a> {
-    type Item = Result<Transaction, Error>;
+impl<'a, R: io::Read> Iterator for CsvTransactionIterator<'a, R> {
+    type Item = TransactionRow;
 
     fn next(&mut self) -> Option<Self::Item> {
-        let next_result = self.0.next()?;
-        Some(
-            next_result
+        let mut record = StringRecord::new();
+
+        let transaction = match self.reader.read_record(&mut record) {
+            Ok(false) => return None,
+            Ok(true) => record
+                .deserialize::<TransactionRecord>(Some(&self.headers))
                 .map_err(Error::Csv)
-                .and_then(|r| r.try_into().map_err(Error::InvalidTransaction)),
-        )
+                .and_then(|r| {
+                    r.into_transaction(self.type_aliases, &self.precision)
+                        .map_err(Error::InvalidTransaction)
+                }),
+            Err(err) => Err(Error::Csv(err)),
+        };
+
+        let position = match &transaction {
+            Err(Error::Csv(err)) => err.position().or_else(|| record.position()),
+            _ => record.position(),
+        };
+        let line = position.map_or(0, |position| position.line());
+
+        Some(TransactionRow {
+            line,
+            fields: self.columns.raw_fields(&record),
+            transaction,
+        })
     }
 }
 
-impl TryFrom<TransactionRecord> for Transaction {
-    type Error = InvalidTransactionError;
+impl TransactionRecord {
+    /// Validates the record, which is shared by all input formats so that they accept
+    /// exactly the same transactions
+    pub(crate) fn into_transaction(
+        self,
+        type_aliases: &TypeAliases,
+        precision: &InputPrecision,
+    ) -> Result<Transaction, InvalidTransactionError> {
+        let tx_type = type_aliases.resolve(&self.tx_type)?.into();
+
+        let amount = self
+            .amount
+            .as_deref()
+            .map(|amount| parse_amount(amount, precision))
+            .transpose()?;
+
+        // Reason codes are only recorded against administrative transactions
+        if self.reason.is_some() && !matches!(tx_type, TransactionType::Admin(_)) {
+            return Err(InvalidTransactionError::ReasonUnexpected);
+        }
 
-    fn try_from(record: TransactionRecord) -> Result<Self, Self::Error> {
-        let transaction = match record.tx_type.into() {
-            TransactionType::Standard(tx_type) => Transaction::Standard(StandardTransaction {
-                tx_type: tx_type,
-                client_id: record.client_id,
-                tx_id: record.tx_id,
-                amount: record
-                    .amount
-                    .ok_or(InvalidTransactionError::AmountNotSpecified)?,
-                dispute_status: None,
-            }),
+        let transaction = match tx_type {
+            TransactionType::Standard(tx_type) => {
+                if self.dispute_id.is_some() {
+                    return Err(InvalidTransactionError::DisputeIdUnexpected);
+                }
+
+                Transaction::Standard(StandardTransaction {
+                    tx_type,
+                    client_id: self.client_id,
+                    tx_id: self.tx_id,
+                    amount: amount.ok_or(InvalidTransactionError::AmountNotSpecified)?,
+                    disputes: Vec::new(),
+                })
+            }
             TransactionType::Dispute(tx_type) => {
-                if record.amount.is_some() {
+                // Only disputes may be for a partial amount. Resolving or charging back always
+                // applies to the full amount of the dispute.
+                if amount.is_some() && tx_type != DisputeTransactionType::Dispute {
                     return Err(InvalidTransactionError::AmountUnexpectedForDispute);
                 }
 
                 Transaction::Dispute(DisputeTransaction {
-                    tx_type: tx_type,
-                    client_id: record.client_id,
-                    tx_id: record.tx_id,
+                    tx_type,
+                    client_id: self.client_id,
+                    tx_id: self.tx_id,
+                    dispute_id: self.dispute_id,
+                    amount,
+                })
+            }
+            TransactionType::Admin(tx_type) => {
+                if self.dispute_id.is_some() {
+                    return Err(InvalidTransactionError::DisputeIdUnexpected);
+                }
+
+                // Only adjustments change balances, and they must always be explained
+                let amount = match tx_type {
+                    AdminTransactionType::Adjustment => {
+                        if self.reason.is_none() {
+                            return Err(InvalidTransactionError::ReasonNotSpecified);
+                        }
+                        Some(amount.ok_or(InvalidTransactionError::AmountNotSpecified)?)
+                    }
+                    _ => {
+                        if amount.is_some() {
+                            return Err(InvalidTransactionError::AmountUnexpectedForAdmin);
+                        }
+                        None
+                    }
+                };
+
+                Transaction::Admin(AdminTransaction {
+                    tx_type,
+                    client_id: self.client_id,
+                    tx_id: self.tx_id,
+                    amount,
+                    reason: self.reason,
                 })
             }
         };
@@ -73,11 +235,13 @@ impl TryFrom<TransactionRecord> for Transaction {
     }
 }
 
-/// CSV-serializable version of a transaction
+/// Serializable version of a transaction, as read from CSV or JSON Lines input
 #[derive(Debug, Deserialize)]
-struct TransactionRecord {
+pub(crate) struct TransactionRecord {
+    /// Type names are resolved with [`TypeAliases::resolve`] rather than deserialized
+    /// directly, so that they can be matched case-insensitively and against aliases
     #[serde(rename = "type")]
-    tx_type: CsvTransactionType,
+    tx_type: String,
 
     #[serde(rename = "client")]
     client_id: u16,
@@ -85,7 +249,32 @@ struct TransactionRecord {
     #[serde(rename = "tx")]
     tx_id: u32,
 
-    amount: Option<Decimal>,
+    /// Amounts are parsed with [`parse_amount`] rather than deserialized directly, so that
+    /// they are exact rather than going via a float, and can be checked for excess precision
+    amount: Option<String>,
+
+    /// Optional column which identifies a dispute, so that several disputes can be
+    /// open against the same transaction
+    #[serde(rename = "dispute")]
+    dispute_id: Option<u32>,
+
+    /// Optional column with a reason code for administrative transactions
+    reason: Option<String>,
+}
+
+fn parse_amount(
+    amount: &str,
+    precision: &InputPrecision,
+) -> Result<Decimal, InvalidTransactionError> {
+    let amount = Decimal::from_str(amount)
+        .or_else(|_| Decimal::from_scientific(amount))
+        .map_err(|_| InvalidTransactionError::AmountInvalid(amount.to_string()))?;
+
+    precision
+        .apply(amount)
+        .ok_or(InvalidTransactionError::AmountTooPrecise(
+            precision.decimal_places,
+        ))
 }
 
 /// This is a temporary type that is used to simplify conversion from
@@ -93,6 +282,7 @@ struct TransactionRecord {
 enum TransactionType {
     Standard(StandardTransactionType),
     Dispute(DisputeTransactionType),
+    Admin(AdminTransactionType),
 }
 
 impl From<CsvTransactionType> for TransactionType {
@@ -113,32 +303,131 @@ impl From<CsvTransactionType> for TransactionType {
             CsvTransactionType::Chargeback => {
                 TransactionType::Dispute(DisputeTransactionType::Chargeback)
             }
+            CsvTransactionType::Unlock => TransactionType::Admin(AdminTransactionType::Unlock),
+            CsvTransactionType::Freeze => TransactionType::Admin(AdminTransactionType::Freeze),
+            CsvTransactionType::Close => TransactionType::Admin(AdminTransactionType::Close),
+            CsvTransactionType::Adjustment => {
+                TransactionType::Admin(AdminTransactionType::Adjustment)
+            }
         }
     }
 }
 
-#[derive(Debug, Deserialize)]
-#[serde(rename_all = "lowercase")] // Note that currently only lowercase types are supported when deserializing
+#[derive(Clone, Copy, Debug, PartialEq)]
 enum CsvTransactionType {
     Deposit,
     Withdrawal,
     Dispute,
     Resolve,
     Chargeback,
+    Unlock,
+    Freeze,
+    Close,
+    Adjustment,
+}
+
+impl CsvTransactionType {
+    /// Parses the standard name of a transaction type, ignoring case
+    fn from_name(name: &str) -> Option<CsvTransactionType> {
+        match name.to_lowercase().as_str() {
+            "deposit" => Some(CsvTransactionType::Deposit),
+            "withdrawal" => Some(CsvTransactionType::Withdrawal),
+            "dispute" => Some(CsvTransactionType::Dispute),
+            "resolve" => Some(CsvTransactionType::Resolve),
+            "chargeback" => Some(CsvTransactionType::Chargeback),
+            "unlock" => Some(CsvTransactionType::Unlock),
+            "freeze" => Some(CsvTransactionType::Freeze),
+            "close" => Some(CsvTransactionType::Close),
+            "adjustment" => Some(CsvTransactionType::Adjustment),
+            _ => None,
+        }
+    }
+}
+
+/// Alternative name for a transaction type, parsed from `ALIAS=TYPE` (e.g. `withdraw=withdrawal`)
+#[derive(Clone, Debug)]
+pub(crate) struct TypeAlias {
+    alias: String,
+    tx_type: CsvTransactionType,
+}
+
+impl FromStr for TypeAlias {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (alias, name) = s
+            .split_once('=')
+            .ok_or_else(|| format!("Expected ALIAS=TYPE but found '{}'", s))?;
+
+        let tx_type = CsvTransactionType::from_name(name.trim())
+            .ok_or_else(|| format!("Unknown transaction type '{}'", name.trim()))?;
+
+        Ok(TypeAlias {
+            alias: alias.trim().to_lowercase(),
+            tx_type,
+        })
+    }
+}
+
+/// Table of aliases which are accepted in addition to the standard transaction type names.
+/// Both standard names and aliases are matched case-insensitively.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct TypeAliases(HashMap<String, CsvTransactionType>);
+
+impl TypeAliases {
+    fn resolve(&self, name: &str) -> Result<CsvTransactionType, InvalidTransactionError> {
+        CsvTransactionType::from_name(name)
+            .or_else(|| self.0.get(&name.to_lowercase()).copied())
+            .ok_or_else(|| InvalidTransactionError::UnknownType(name.to_string()))
+    }
+}
+
+impl FromIterator<TypeAlias> for TypeAliases {
+    fn from_iter<I: IntoIterator<Item = TypeAlias>>(iter: I) -> Self {
+        TypeAliases(
+            iter.into_iter()
+                .map(|type_alias| (type_alias.alias, type_alias.tx_type))
+                .collect(),
+        )
+    }
 }
 
 #[derive(Debug)]
 pub enum Error {
     Csv(csv::Error),
+    Json(serde_json::Error),
     InvalidTransaction(InvalidTransactionError),
 }
 
+impl Error {
+    /// Short machine-readable identifier for the kind of error, e.g. for reports
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::Csv(_) => "invalid_csv",
+            Self::Json(_) => "invalid_json",
+            Self::InvalidTransaction(err) => err.kind(),
+        }
+    }
+
+    /// Whether the error prevents any further rows from being read, e.g. I/O errors, as
+    /// opposed to errors which only affect a single row
+    pub fn is_fatal(&self) -> bool {
+        match self {
+            Self::Csv(err) => err.is_io_error(),
+            Self::Json(err) => err.is_io(),
+            Self::InvalidTransaction(_) => false,
+        }
+    }
+}
+
 impl std::error::Error for Error {}
 
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            Self::Csv(err) => write!(f, "CSV error: {}", err),
+            // The underlying error already describes itself as a CSV error
+            Self::Csv(err) => write!(f, "{}", err),
+            Self::Json(err) => write!(f, "JSON error: {}", err),
             Self::InvalidTransaction(err) => write!(f, "Invalid transaction: {}", err),
         }
     }
@@ -146,8 +435,32 @@ impl fmt::Display for Error {
 
 #[derive(Debug)]
 pub enum InvalidTransactionError {
+    UnknownType(String),
     AmountNotSpecified,
+    AmountInvalid(String),
+    AmountTooPrecise(u32),
     AmountUnexpectedForDispute,
+    AmountUnexpectedForAdmin,
+    DisputeIdUnexpected,
+    ReasonNotSpecified,
+    ReasonUnexpected,
+}
+
+impl InvalidTransactionError {
+    /// Short machine-readable identifier for the kind of error, e.g. for reports
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::UnknownType(_) => "unknown_type",
+            Self::AmountNotSpecified => "amount_not_specified",
+            Self::AmountInvalid(_) => "amount_invalid",
+            Self::AmountTooPrecise(_) => "amount_too_precise",
+            Self::AmountUnexpectedForDispute => "amount_unexpected_for_dispute",
+            Self::AmountUnexpectedForAdmin => "amount_unexpected_for_admin",
+            Self::DisputeIdUnexpected => "dispute_id_unexpected",
+            Self::ReasonNotSpecified => "reason_not_specified",
+            Self::ReasonUnexpected => "reason_unexpected",
+        }
+    }
 }
 
 // TODO: Is this even used...? Not sure why but it doesn't seem to be used to format
@@ -155,13 +468,43 @@ pub enum InvalidTransactionError {
 impl fmt::Display for InvalidTransactionError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
+            Self::UnknownType(name) => {
+                write!(f, "Unknown transaction type: {}", name)
+            }
             Self::AmountNotSpecified => {
                 write!(f, "Amount not specified")
             }
+            Self::AmountInvalid(amount) => {
+                write!(f, "Invalid amount: {}", amount)
+            }
+            Self::AmountTooPrecise(decimal_places) => {
+                write!(f, "Amount has more than {} decimal places", decimal_places)
+            }
             Self::AmountUnexpectedForDispute => {
                 write!(
                     f,
-                    "Amount should not be specified for dispute/resolve/chargeback transactions"
+                    "Amount should not be specified for resolve/chargeback transactions"
+                )
+            }
+            Self::DisputeIdUnexpected => {
+                write!(
+                    f,
+                    "Dispute id should only be specified for dispute/resolve/chargeback transactions"
+                )
+            }
+            Self::AmountUnexpectedForAdmin => {
+                write!(
+                    f,
+                    "Amount should not be specified for unlock/freeze/close transactions"
+                )
+            }
+            Self::ReasonNotSpecified => {
+                write!(f, "Reason not specified for adjustment")
+            }
+            Self::ReasonUnexpected => {
+                write!(
+                    f,
+                    "Reason should only be specified for unlock/freeze/close/adjustment transactions"
                 )
             }
         }
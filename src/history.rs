@@ -0,0 +1,147 @@
+use std::collections::{HashMap, HashSet};
+
+use rust_decimal::Decimal;
+
+use crate::ledger::{Account, LedgerError, Outcome, Transaction};
+
+/// Chronological record of every transaction processed for each client, including refused
+/// transactions, along with the client's balances after each one. Used to produce
+/// statements, e.g. so that support staff can explain how a balance was reached.
+///
+/// Entries are kept in memory for the lifetime of the history, so it can be limited to a
+/// set of clients.
+#[derive(Debug, Default)]
+pub(crate) struct History {
+    /// Clients to record transactions for, or `None` to record every client
+    client_ids: Option<HashSet<u16>>,
+    entries_by_client_id: HashMap<u16, Vec<HistoryEntry>>,
+}
+
+/// A transaction processed for a client, and its effect on the client's account
+#[derive(Clone, Debug)]
+pub(crate) struct HistoryEntry {
+    /// Where the transaction was read from, e.g. the input path
+    pub source: String,
+    /// Line number of the transaction within the source
+    pub line: u64,
+    pub tx_type: &'static str,
+    pub tx_id: u32,
+    pub dispute_id: Option<u32>,
+    pub amount: Option<Decimal>,
+    /// Reason code given for an administrative transaction
+    pub reason_code: Option<String>,
+    pub result: Result<Outcome, LedgerError>,
+    /// The client's account after the transaction was processed. Clients without an account
+    /// (e.g. if their first deposit was refused) have a new, empty account.
+    pub account: Account,
+}
+
+/// Details of a transaction which are kept in the history, taken before the transaction is
+/// handed to the ledger
+#[derive(Clone, Debug)]
+pub(crate) struct TransactionSummary {
+    tx_type: &'static str,
+    client_id: u16,
+    tx_id: u32,
+    dispute_id: Option<u32>,
+    amount: Option<Decimal>,
+    reason_code: Option<String>,
+}
+
+impl History {
+    pub fn new() -> History {
+        History::default()
+    }
+
+    /// Only records transactions for the given clients
+    pub fn with_clients<I: IntoIterator<Item = u16>>(mut self, client_ids: I) -> History {
+        self.client_ids = Some(client_ids.into_iter().collect());
+        self
+    }
+
+    /// Summarizes the transaction, if it's for a client whose transactions are recorded
+    pub fn summarize(&self, transaction: &Transaction) -> Option<TransactionSummary> {
+        let client_id = transaction.client_id();
+        if let Some(client_ids) = &self.client_ids {
+            if !client_ids.contains(&client_id) {
+                return None;
+            }
+        }
+
+        let (tx_id, dispute_id, amount, reason_code) = match transaction {
+            Transaction::Standard(transaction) => {
+                (transaction.tx_id, None, Some(transaction.amount), None)
+            }
+            Transaction::Dispute(transaction) => (
+                transaction.tx_id,
+                transaction.dispute_id,
+                transaction.amount,
+                None,
+            ),
+            Transaction::Admin(transaction) => (
+                transaction.tx_id,
+                None,
+                transaction.amount,
+                transaction.reason.clone(),
+            ),
+        };
+
+        Some(TransactionSummary {
+            tx_type: transaction.type_name(),
+            client_id,
+            tx_id,
+            dispute_id,
+            amount,
+            reason_code,
+        })
+    }
+
+    /// Records the result of processing a summarized transaction, given the client's account
+    /// afterwards
+    pub fn record(
+        &mut self,
+        transaction: TransactionSummary,
+        source: &str,
+        line: u64,
+        result: Result<Outcome, LedgerError>,
+        account: Option<Account>,
+    ) {
+        let TransactionSummary {
+            tx_type,
+            client_id,
+            tx_id,
+            dispute_id,
+            amount,
+            reason_code,
+        } = transaction;
+
+        self.entries_by_client_id
+            .entry(client_id)
+            .or_default()
+            .push(HistoryEntry {
+                source: source.to_string(),
+                line,
+                tx_type,
+                tx_id,
+                dispute_id,
+                amount,
+                reason_code,
+                result,
+                account: account.unwrap_or_else(|| Account::new(client_id)),
+            });
+    }
+
+    /// Every transaction processed for the client, in the order they were processed
+    pub fn get_entries(&self, client_id: u16) -> &[HistoryEntry] {
+        self.entries_by_client_id
+            .get(&client_id)
+            .map_or(&[], Vec::as_slice)
+    }
+
+    /// Ids of every client with at least one entry, in ascending order
+    pub fn client_ids(&self) -> Vec<u16> {
+        let mut client_ids: Vec<u16> = self.entries_by_client_id.keys().copied().collect();
+        client_ids.sort_unstable();
+        client_ids
+    }
+}
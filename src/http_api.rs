@@ -0,0 +1,323 @@
+use std::{
+    io,
+    net::{SocketAddr, ToSocketAddrs},
+    sync::Arc,
+    thread,
+};
+
+use rust_decimal::Decimal;
+use serde::Serialize;
+use tiny_http::{Header, Method, Request, Response};
+
+use crate::{
+    accounts_output,
+    amounts::OutputScale,
+    json_accounts::{self, AccountRecord},
+    ledger::{DisputeStatus, StandardTransaction, StandardTransactionType},
+    server::{Acknowledgement, AcknowledgementResult, InputOptions, SharedLedger},
+    statement, transactions_input,
+};
+
+/// HTTP API for submitting transactions to a [`SharedLedger`] and reading its current
+/// state, so that balances can be read while transactions are still being processed:
+///
+/// - `POST /transactions`: processes the transactions in the request body, which is in the
+///   same format as an input file, and returns an array of [`Acknowledgement`]s.
+/// - `GET /accounts`: returns every account, ordered by client id.
+/// - `GET /accounts/{client}`: returns a single account.
+/// - `GET /accounts/{client}/statement`: returns every transaction processed for the client
+///   and the balances after each one, if the ledger is recording its history.
+/// - `GET /transactions/{tx}`: returns a deposit or withdrawal, including its disputes.
+///
+/// All responses are JSON, with accounts in the same format as `--output-format json`.
+pub(crate) struct HttpApi {
+    server: tiny_http::Server,
+    ledger: Arc<SharedLedger>,
+    input: InputOptions,
+    scale: OutputScale,
+}
+
+impl HttpApi {
+    pub fn bind<A: ToSocketAddrs>(
+        addr: A,
+        ledger: Arc<SharedLedger>,
+        input: InputOptions,
+        scale: OutputScale,
+    ) -> io::Result<HttpApi> {
+        Ok(HttpApi {
+            server: tiny_http::Server::http(addr).map_err(io::Error::other)?,
+            ledger,
+            input,
+            scale,
+        })
+    }
+
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        self.server.server_addr().to_ip()
+    }
+
+    /// Handles requests until the process is stopped, each one on its own thread
+    pub fn run(self) {
+        let api = Arc::new(self);
+
+        for mut request in api.server.incoming_requests() {
+            let api = Arc::clone(&api);
+            thread::spawn(move || {
+                let response = api.respond(&mut request);
+                if let Err(err) = request.respond(response) {
+                    eprintln!("Failed to send HTTP response: {}", err);
+                }
+            });
+        }
+    }
+
+    fn respond(&self, request: &mut Request) -> Response<io::Cursor<Vec<u8>>> {
+        // Query strings aren't used, so they are ignored
+        let path = request.url().split('?').next().unwrap_or_default();
+        let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+
+        match (request.method(), segments.as_slice()) {
+            (Method::Post, ["transactions"]) => self.submit_transactions(request),
+            (Method::Get, ["accounts"]) => self.get_accounts(),
+            (Method::Get, ["accounts", client]) => match client.parse() {
+                Ok(client_id) => self.get_account(client_id),
+                Err(_) => error_response(400, "invalid_client", "Client id must be a u16"),
+            },
+            (Method::Get, ["accounts", client, "statement"]) => match client.parse() {
+                Ok(client_id) => self.get_statement(client_id),
+                Err(_) => error_response(400, "invalid_client", "Client id must be a u16"),
+            },
+            (Method::Get, ["transactions", tx]) => match tx.parse() {
+                Ok(tx_id) => self.get_transaction(tx_id),
+                Err(_) => error_response(400, "invalid_tx", "Transaction id must be a u32"),
+            },
+            (
+                _,
+                ["transactions"]
+                | ["accounts"]
+                | ["accounts", _]
+                | ["accounts", _, "statement"]
+                | ["transactions", _],
+            ) => error_response(405, "method_not_allowed", "Method not allowed"),
+            _ => error_response(404, "not_found", "Not found"),
+        }
+    }
+
+    fn submit_transactions(&self, request: &mut Request) -> Response<io::Cursor<Vec<u8>>> {
+        let format = content_type(request)
+            .and_then(format_for_content_type)
+            .unwrap_or(self.input.format);
+
+        let source = request
+            .remote_addr()
+            .map_or_else(|| "unknown peer".to_string(), |addr| addr.to_string());
+
+        let mut reader = transactions_input::Reader::from_reader(request.as_reader(), format)
+            .with_type_aliases(self.input.type_aliases.clone())
+            .with_precision(self.input.precision);
+
+        let rows = match reader.iter() {
+            Ok(rows) => rows,
+            Err(err) => return error_response(400, err.kind(), &err.to_string()),
+        };
+
+        let mut acks: Vec<Acknowledgement> = Vec::new();
+        for row in rows {
+            let line = row.line;
+            match self.ledger.handle_row(row, &source) {
+                Ok(ack) => acks.push(ack),
+                Err(err) => {
+                    // Nothing more can be read from the body, but the rows before the error
+                    // have already been processed, so they are still acknowledged
+                    acks.push(Acknowledgement {
+                        line,
+                        tx_type: String::new(),
+                        client: String::new(),
+                        tx: String::new(),
+                        result: AcknowledgementResult::Failed,
+                        reason: Some(err.kind()),
+                        message: Some(err.to_string()),
+                    });
+                    break;
+                }
+            }
+        }
+
+        json_response(200, &acks)
+    }
+
+    fn get_accounts(&self) -> Response<io::Cursor<Vec<u8>>> {
+        let mut body = Vec::new();
+        let result = self.ledger.read(|ledger| {
+            let mut accounts: Vec<_> = ledger.get_accounts().collect();
+            accounts.sort_by_key(|account| account.client_id);
+
+            json_accounts::Writer::from_writer(&mut body)
+                .with_scale(self.scale)
+                .write_all(accounts.into_iter())
+        });
+
+        match result {
+            Ok(()) => Response::from_data(body).with_header(json_content_type()),
+            Err(err) => error_response(500, "internal_error", &err.to_string()),
+        }
+    }
+
+    fn get_account(&self, client_id: u16) -> Response<io::Cursor<Vec<u8>>> {
+        match self.ledger.read(|ledger| ledger.get_account(client_id)) {
+            Some(account) => json_response(200, &AccountRecord::new(&account, &self.scale)),
+            None => error_response(
+                404,
+                "unknown_account",
+                &format!("No account for client id {}", client_id),
+            ),
+        }
+    }
+
+    fn get_statement(&self, client_id: u16) -> Response<io::Cursor<Vec<u8>>> {
+        let mut body = Vec::new();
+        let result = self.ledger.read_history(|history| {
+            statement::Writer::from_writer(&mut body, accounts_output::Format::Json, self.scale)
+                .write_all(history.get_entries(client_id).iter())
+        });
+
+        match result {
+            Some(Ok(())) => Response::from_data(body).with_header(json_content_type()),
+            Some(Err(err)) => error_response(500, "internal_error", &err.to_string()),
+            None => error_response(
+                404,
+                "history_disabled",
+                "Statements are only available when the server is started with --history",
+            ),
+        }
+    }
+
+    fn get_transaction(&self, tx_id: u32) -> Response<io::Cursor<Vec<u8>>> {
+        match self.ledger.read(|ledger| ledger.get_transaction(tx_id)) {
+            Some(transaction) => {
+                json_response(200, &TransactionRecord::new(&transaction, &self.scale))
+            }
+            None => error_response(
+                404,
+                "unknown_transaction",
+                &format!("No deposit or withdrawal with transaction id {}", tx_id),
+            ),
+        }
+    }
+}
+
+/// A deposit or withdrawal, along with its disputes
+#[derive(Serialize)]
+struct TransactionRecord {
+    #[serde(rename = "type")]
+    tx_type: &'static str,
+    client: u16,
+    tx: u32,
+    amount: Decimal,
+    /// `undisputed` if there are no disputes, `disputed` if any dispute is still open,
+    /// `charged_back` if any dispute was charged back, and otherwise `resolved`
+    status: &'static str,
+    disputes: Vec<DisputeRecord>,
+}
+
+#[derive(Serialize)]
+struct DisputeRecord {
+    dispute: Option<u32>,
+    amount: Decimal,
+    status: &'static str,
+}
+
+impl TransactionRecord {
+    fn new(transaction: &StandardTransaction, scale: &OutputScale) -> Self {
+        let disputes: Vec<_> = transaction
+            .disputes
+            .iter()
+            .map(|dispute| DisputeRecord {
+                dispute: dispute.dispute_id,
+                amount: scale.apply(dispute.amount),
+                status: match dispute.status {
+                    DisputeStatus::Unresolved => "disputed",
+                    DisputeStatus::Resolved => "resolved",
+                    DisputeStatus::Chargeback => "charged_back",
+                },
+            })
+            .collect();
+
+        let has_status = |status| disputes.iter().any(|dispute| dispute.status == status);
+        let status = if disputes.is_empty() {
+            "undisputed"
+        } else if has_status("disputed") {
+            "disputed"
+        } else if has_status("charged_back") {
+            "charged_back"
+        } else {
+            "resolved"
+        };
+
+        TransactionRecord {
+            tx_type: match transaction.tx_type {
+                StandardTransactionType::Deposit => "deposit",
+                StandardTransactionType::Withdrawal => "withdrawal",
+            },
+            client: transaction.client_id,
+            tx: transaction.tx_id,
+            amount: scale.apply(transaction.amount),
+            status,
+            disputes,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct ErrorRecord<'a> {
+    error: &'a str,
+    message: &'a str,
+}
+
+fn content_type(request: &Request) -> Option<&str> {
+    request
+        .headers()
+        .iter()
+        .find(|header| header.field.equiv("Content-Type"))
+        .map(|header| header.value.as_str())
+}
+
+/// Transactions may be submitted in either input format, regardless of `--input-format`,
+/// by giving the content type of the body
+fn format_for_content_type(content_type: &str) -> Option<transactions_input::Format> {
+    let media_type = content_type.split(';').next().unwrap_or_default().trim();
+
+    match media_type.to_lowercase().as_str() {
+        "text/csv" => Some(transactions_input::Format::Csv),
+        "application/json" | "application/jsonl" | "application/x-ndjson" => {
+            Some(transactions_input::Format::Jsonl)
+        }
+        _ => None,
+    }
+}
+
+fn json_content_type() -> Header {
+    Header::from_bytes("Content-Type", "application/json").expect("header is valid")
+}
+
+fn json_response<T: Serialize>(status: u16, value: &T) -> Response<io::Cursor<Vec<u8>>> {
+    match serde_json::to_vec(value) {
+        Ok(mut body) => {
+            body.push(b'\n');
+            Response::from_data(body)
+                .with_status_code(status)
+                .with_header(json_content_type())
+        }
+        Err(err) => error_response(500, "internal_error", &err.to_string()),
+    }
+}
+
+fn error_response(status: u16, error: &str, message: &str) -> Response<io::Cursor<Vec<u8>>> {
+    // Serializing two strings can't fail
+    let mut body = serde_json::to_vec(&ErrorRecord { error, message }).unwrap_or_default();
+    body.push(b'\n');
+
+    Response::from_data(body)
+        .with_status_code(status)
+        .with_header(json_content_type())
+}
@@ -0,0 +1,160 @@
+use std::{fmt, io};
+
+use crate::{
+    audit_log::{self, AuditLog, TransactionDetails},
+    compact_ledger::CompactLedger,
+    hashmap_ledger::HashMapLedger,
+    ledger::{
+        Account, Ledger, LedgerError, LedgerStorage, Outcome, StandardTransaction, Transaction,
+    },
+    ledger_engine::LedgerEngine,
+    wal_ledger::{self, WalLedger},
+};
+
+/// Ledger which processes each transaction as soon as it's submitted, so that the result is
+/// known immediately, using whichever storage backend was chosen on the command line.
+/// Changes to accounts are optionally recorded in an [`AuditLog`].
+///
+/// Unlike [`LedgerError`]s, which only refuse a single transaction, an [`Error`] means that
+/// the storage backend or audit log has failed and the ledger can no longer be relied on.
+pub(crate) struct ImmediateLedger {
+    backend: Backend,
+    audit_log: Option<AuditLog>,
+}
+
+pub(crate) enum Backend {
+    InMemory(LedgerEngine<HashMapLedger>),
+    Durable(LedgerEngine<WalLedger<HashMapLedger>>),
+    Compact(LedgerEngine<CompactLedger>),
+}
+
+impl ImmediateLedger {
+    pub fn new(backend: Backend) -> ImmediateLedger {
+        ImmediateLedger {
+            backend,
+            audit_log: None,
+        }
+    }
+
+    /// Records an event in the audit log for every transaction which is applied
+    pub fn with_audit_log(mut self, audit_log: AuditLog) -> ImmediateLedger {
+        self.audit_log = Some(audit_log);
+        self
+    }
+
+    pub fn backend(&self) -> &Backend {
+        &self.backend
+    }
+
+    pub fn handle_transaction(
+        &mut self,
+        transaction: Transaction,
+    ) -> Result<Result<Outcome, LedgerError>, Error> {
+        let client_id = transaction.client_id();
+        let audited = self.audit_log.as_ref().map(|_| {
+            (
+                TransactionDetails::new(&transaction),
+                self.backend.get_account(client_id),
+            )
+        });
+
+        let result = self.backend.handle_transaction(transaction)?;
+
+        if let (Some(audit_log), Some((details, before)), Ok(Outcome::Applied)) =
+            (&mut self.audit_log, audited, result)
+        {
+            // Applied transactions always leave the client with an account
+            if let Some(after) = self.backend.get_account(client_id) {
+                audit_log
+                    .record(&details, before.as_ref(), &after)
+                    .map_err(Error::AuditLog)?;
+            }
+        }
+
+        Ok(result)
+    }
+
+    pub fn get_accounts(&self) -> Box<dyn Iterator<Item = &Account> + '_> {
+        match &self.backend {
+            Backend::InMemory(ledger) => Box::new(ledger.get_accounts()),
+            Backend::Durable(ledger) => Box::new(ledger.get_accounts()),
+            Backend::Compact(ledger) => Box::new(ledger.get_accounts()),
+        }
+    }
+
+    pub fn get_account(&self, client_id: u16) -> Option<Account> {
+        self.backend.get_account(client_id)
+    }
+
+    /// Returns the deposit or withdrawal with the given id, including its disputes
+    pub fn get_transaction(&self, tx_id: u32) -> Option<StandardTransaction> {
+        match &self.backend {
+            Backend::InMemory(ledger) => ledger.storage().get_transaction(tx_id),
+            Backend::Durable(ledger) => ledger.storage().get_transaction(tx_id),
+            Backend::Compact(ledger) => ledger.storage().get_transaction(tx_id),
+        }
+    }
+
+    /// Waits for any changes to the ledger and audit log to be written to disk
+    pub fn sync(&mut self) -> Result<(), Error> {
+        if let Backend::Durable(ledger) = &mut self.backend {
+            ledger.storage_mut().sync().map_err(Error::Wal)?;
+        }
+
+        match &mut self.audit_log {
+            Some(audit_log) => audit_log.sync().map_err(Error::AuditLog),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Backend {
+    fn handle_transaction(
+        &mut self,
+        transaction: Transaction,
+    ) -> Result<Result<Outcome, LedgerError>, Error> {
+        match self {
+            Backend::InMemory(ledger) => Ok(ledger.handle_transaction(transaction)),
+            Backend::Compact(ledger) => {
+                let result = ledger.handle_transaction(transaction);
+                ledger.storage_mut().check().map_err(Error::Index)?;
+                Ok(result)
+            }
+            Backend::Durable(ledger) => {
+                let result = ledger.handle_transaction(transaction);
+                // Transactions are validated before anything is committed, so refused and
+                // replayed transactions never write to the log. This only flushes the entry
+                // written by an applied transaction, and reports any error writing it.
+                ledger.storage_mut().commit().map_err(Error::Wal)?;
+                Ok(result)
+            }
+        }
+    }
+
+    fn get_account(&self, client_id: u16) -> Option<Account> {
+        match self {
+            Backend::InMemory(ledger) => ledger.storage().get_account(client_id),
+            Backend::Durable(ledger) => ledger.storage().get_account(client_id),
+            Backend::Compact(ledger) => ledger.storage().get_account(client_id),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum Error {
+    Wal(wal_ledger::Error),
+    Index(io::Error),
+    AuditLog(audit_log::Error),
+}
+
+impl std::error::Error for Error {}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Wal(err) => write!(f, "{}", err),
+            Self::Index(err) => write!(f, "Failed to access transaction index file: {}", err),
+            Self::AuditLog(err) => write!(f, "{}", err),
+        }
+    }
+}
@@ -0,0 +1,95 @@
+use std::io;
+
+use rust_decimal::Decimal;
+use serde::Serialize;
+
+use crate::amounts::OutputScale;
+use crate::ledger::Account;
+
+/// Writes accounts either as a single JSON array, or as JSON Lines with one account per line
+pub(crate) struct Writer<W> {
+    writer: W,
+    lines: bool,
+    scale: OutputScale,
+}
+
+impl<W> Writer<W> {
+    pub fn from_writer(writer: W) -> Writer<W> {
+        Writer {
+            writer,
+            lines: false,
+            scale: OutputScale::default(),
+        }
+    }
+
+    pub fn from_writer_lines(writer: W) -> Writer<W> {
+        Writer {
+            writer,
+            lines: true,
+            scale: OutputScale::default(),
+        }
+    }
+
+    pub fn with_scale(mut self, scale: OutputScale) -> Writer<W> {
+        self.scale = scale;
+        self
+    }
+}
+
+impl<W: io::Write> Writer<W> {
+    pub fn write_all<'a, I: Iterator<Item = &'a Account>>(
+        mut self,
+        accounts_iterator: I,
+    ) -> Result<(), serde_json::Error> {
+        // Accounts are written as they are iterated rather than collected into a `Vec`
+        // first, so the array delimiters are written by hand.
+        if !self.lines {
+            self.writer.write_all(b"[").map_err(serde_json::Error::io)?;
+        }
+
+        for (index, account) in accounts_iterator.enumerate() {
+            if !self.lines && index > 0 {
+                self.writer.write_all(b",").map_err(serde_json::Error::io)?;
+            }
+
+            serde_json::to_writer(&mut self.writer, &AccountRecord::new(account, &self.scale))?;
+
+            if self.lines {
+                self.writer
+                    .write_all(b"\n")
+                    .map_err(serde_json::Error::io)?;
+            }
+        }
+
+        if !self.lines {
+            self.writer
+                .write_all(b"]\n")
+                .map_err(serde_json::Error::io)?;
+        }
+
+        self.writer.flush().map_err(serde_json::Error::io)
+    }
+}
+
+/// Same fields as the CSV output. Amounts are serialized as strings so that no precision
+/// is lost by consumers which parse JSON numbers as floats.
+#[derive(Serialize)]
+pub(crate) struct AccountRecord {
+    client: u16,
+    available: Decimal,
+    held: Decimal,
+    total: Decimal,
+    locked: bool,
+}
+
+impl AccountRecord {
+    pub fn new(account: &Account, scale: &OutputScale) -> Self {
+        AccountRecord {
+            client: account.client_id,
+            available: scale.apply(account.available),
+            held: scale.apply(account.held),
+            total: scale.apply(account.total()),
+            locked: account.is_locked,
+        }
+    }
+}
@@ -0,0 +1,100 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, BufRead, BufReader, Seek, SeekFrom},
+    marker::PhantomData,
+    path::Path,
+};
+
+use serde::de::DeserializeOwned;
+
+/// Reads values written as JSON Lines by an append-only log, such as the write-ahead log or
+/// the audit log. Values are only complete once their trailing newline has been written, so
+/// an incomplete value at the end of the log, e.g. because the program was killed while
+/// writing it, is ignored.
+///
+/// Each value is returned along with its line number, starting from 1.
+pub(crate) struct JsonLines<R, T> {
+    reader: R,
+    buffer: String,
+    line: u64,
+    /// Length of the complete lines which have been read
+    valid_length: u64,
+    value: PhantomData<T>,
+}
+
+impl<R: BufRead, T: DeserializeOwned> JsonLines<R, T> {
+    pub fn new(reader: R) -> JsonLines<R, T> {
+        JsonLines {
+            reader,
+            buffer: String::new(),
+            line: 0,
+            valid_length: 0,
+            value: PhantomData,
+        }
+    }
+}
+
+impl<R: BufRead, T: DeserializeOwned> Iterator for JsonLines<R, T> {
+    type Item = Result<(u64, T), Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.buffer.clear();
+        let read = match self.reader.read_line(&mut self.buffer) {
+            Ok(0) => return None,
+            Ok(read) => read,
+            Err(err) => return Some(Err(Error::Io(err))),
+        };
+        self.line += 1;
+
+        if !self.buffer.ends_with('\n') {
+            return None;
+        }
+
+        Some(match serde_json::from_str(&self.buffer) {
+            Ok(value) => {
+                self.valid_length += read as u64;
+                Ok((self.line, value))
+            }
+            Err(err) => Err(Error::Corrupt {
+                line: self.line,
+                err,
+            }),
+        })
+    }
+}
+
+/// Opens the log at `path` for appending, creating it if it doesn't exist, and passes each
+/// value already in the log to `handle`. An incomplete value at the end of the log is removed,
+/// since appends always go to the end of the file.
+pub(crate) fn open_log<P, T, E, F>(path: P, mut handle: F) -> Result<File, E>
+where
+    P: AsRef<Path>,
+    T: DeserializeOwned,
+    E: From<Error>,
+    F: FnMut(u64, T) -> Result<(), E>,
+{
+    let mut file = OpenOptions::new()
+        .read(true)
+        .append(true)
+        .create(true)
+        .open(path)
+        .map_err(Error::Io)?;
+
+    let mut values = JsonLines::new(BufReader::new(&mut file));
+    for value in &mut values {
+        let (line, value) = value?;
+        handle(line, value)?;
+    }
+    let valid_length = values.valid_length;
+
+    file.set_len(valid_length).map_err(Error::Io)?;
+    file.seek(SeekFrom::End(0)).map_err(Error::Io)?;
+
+    Ok(file)
+}
+
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    Corrupt { line: u64, err: serde_json::Error },
+}
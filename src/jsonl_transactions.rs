@@ -0,0 +1,133 @@
+use std::io::{self, BufRead, BufReader};
+
+use serde_json::Value;
+
+use crate::amounts::InputPrecision;
+use crate::csv_transactions::{Error, RawFields, TransactionRecord, TransactionRow, TypeAliases};
+
+/// Reads transactions from JSON Lines input, with one JSON object per line. Objects have
+/// the same fields as the columns of the transactions CSV, and are validated identically.
+pub(crate) struct Reader<R> {
+    reader: BufReader<R>,
+    type_aliases: TypeAliases,
+    precision: InputPrecision,
+}
+
+impl<R: io::Read> Reader<R> {
+    pub fn from_reader(reader: R) -> Reader<R> {
+        Reader {
+            reader: BufReader::new(reader),
+            type_aliases: TypeAliases::default(),
+            precision: InputPrecision::default(),
+        }
+    }
+
+    /// Accept the given aliases in addition to the standard transaction type names
+    pub fn with_type_aliases(mut self, type_aliases: TypeAliases) -> Reader<R> {
+        self.type_aliases = type_aliases;
+        self
+    }
+
+    /// Limit the number of decimal places of amounts
+    pub fn with_precision(mut self, precision: InputPrecision) -> Reader<R> {
+        self.precision = precision;
+        self
+    }
+
+    pub fn iter(&mut self) -> JsonlTransactionIterator<'_, R> {
+        JsonlTransactionIterator {
+            reader: &mut self.reader,
+            type_aliases: &self.type_aliases,
+            precision: self.precision,
+            line: 0,
+            is_done: false,
+        }
+    }
+}
+
+pub(crate) struct JsonlTransactionIterator<'r, R> {
+    reader: &'r mut BufReader<R>,
+    type_aliases: &'r TypeAliases,
+    precision: InputPrecision,
+    line: u64,
+    is_done: bool,
+}
+
+impl<'a, R: io::Read> Iterator for JsonlTransactionIterator<'a, R> {
+    type Item = TransactionRow;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut buffer = String::new();
+
+        // Blank lines are skipped, e.g. a trailing newline at the end of the input
+        while !self.is_done && buffer.trim().is_empty() {
+            buffer.clear();
+            self.line += 1;
+
+            match self.reader.read_line(&mut buffer) {
+                Ok(0) => return None,
+                Ok(_) => {}
+                Err(err) => {
+                    // Nothing more can be read after an I/O error
+                    self.is_done = true;
+                    return Some(TransactionRow {
+                        line: self.line,
+                        fields: RawFields::default(),
+                        transaction: Err(Error::Json(serde_json::Error::io(err))),
+                    });
+                }
+            }
+        }
+
+        if self.is_done {
+            return None;
+        }
+
+        // Parse into a generic value first so that the fields can still be reported if the
+        // object isn't a valid transaction
+        let value = serde_json::from_str::<Value>(buffer.trim_end());
+        let fields = value.as_ref().map(raw_fields).unwrap_or_default();
+
+        let transaction = value
+            .map(amount_to_string)
+            .and_then(serde_json::from_value::<TransactionRecord>)
+            .map_err(Error::Json)
+            .and_then(|r| {
+                r.into_transaction(self.type_aliases, &self.precision)
+                    .map_err(Error::InvalidTransaction)
+            });
+
+        Some(TransactionRow {
+            line: self.line,
+            fields,
+            transaction,
+        })
+    }
+}
+
+/// Amounts may be given as JSON numbers, but are always parsed from strings like CSV
+/// amounts. Note that JSON numbers may already have lost precision, so amounts should be
+/// given as strings where precision matters.
+fn amount_to_string(mut value: Value) -> Value {
+    if let Some(amount) = value.get_mut("amount") {
+        if let Value::Number(number) = amount {
+            *amount = Value::String(number.to_string());
+        }
+    }
+    value
+}
+
+fn raw_fields(value: &Value) -> RawFields {
+    let field = |name: &str| match value.get(name) {
+        None | Some(Value::Null) => String::new(),
+        Some(Value::String(s)) => s.trim().to_string(),
+        Some(other) => other.to_string(),
+    };
+
+    RawFields {
+        tx_type: field("type"),
+        client: field("client"),
+        tx: field("tx"),
+        amount: field("amount"),
+    }
+}
@@ -1,35 +1,240 @@
+use std::fmt;
+
 use rust_decimal::Decimal;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 
-/// The idea of this trait is that there could be alternate implementations which
-/// share common code for business logic. That hasn't really been fleshed out though
-/// and would need a lot more thought.
+/// A ledger records transactions and tracks account balances. The business rules are
+/// implemented once by [`crate::ledger_engine::LedgerEngine`], which can be used with any
+/// [`LedgerStorage`] backend.
 pub(crate) trait Ledger<'a> {
     type AccountsIterator: Iterator<Item = &'a Account>;
-    type TransactionError;
 
     fn get_accounts(&'a self) -> Self::AccountsIterator;
 
-    fn handle_transaction(
-        &mut self,
-        transaction: Transaction,
-    ) -> Result<(), Self::TransactionError>;
+    fn handle_transaction(&mut self, transaction: Transaction) -> Result<Outcome, LedgerError>;
+}
+
+/// How an accepted transaction affected a [`Ledger`]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Outcome {
+    /// The transaction changed the ledger
+    Applied,
+    /// The transaction is identical to one which has already been applied, e.g. because it
+    /// was delivered more than once, so the ledger wasn't changed
+    Replayed,
+}
+
+/// Storage backend for a ledger. Implementations only store and retrieve accounts and
+/// transactions; they don't apply any business rules, so that every backend behaves
+/// identically.
+pub(crate) trait LedgerStorage<'a> {
+    type AccountsIterator: Iterator<Item = &'a Account>;
+
+    fn get_accounts(&'a self) -> Self::AccountsIterator;
+
+    fn get_account(&self, client_id: u16) -> Option<Account>;
+
+    /// Inserts the account, or replaces the existing account with the same client id
+    fn put_account(&mut self, account: Account);
+
+    fn get_transaction(&self, tx_id: u32) -> Option<StandardTransaction>;
+
+    /// Whether a transaction has been recorded with this id. This may be true even if
+    /// [`LedgerStorage::get_transaction`] returns `None`, for backends which don't keep
+    /// transactions that can't be disputed.
+    fn contains_transaction(&self, tx_id: u32) -> bool {
+        self.get_transaction(tx_id).is_some()
+    }
+
+    /// Inserts the transaction, or replaces the existing transaction with the same id
+    fn put_transaction(&mut self, transaction: StandardTransaction);
+
+    fn get_admin_transaction(&self, tx_id: u32) -> Option<AdminTransaction>;
+
+    /// Inserts the administrative transaction, or replaces the existing administrative
+    /// transaction with the same id
+    fn put_admin_transaction(&mut self, transaction: AdminTransaction);
+
+    /// Stores every change made by a deposit, withdrawal or dispute: the updated account and
+    /// the updated transaction. Backends which persist changes should override this so that
+    /// either all or none of the changes are kept.
+    fn commit(&mut self, account: Account, transaction: StandardTransaction) {
+        self.put_transaction(transaction);
+        self.put_account(account);
+    }
+
+    /// Stores every change made by an administrative transaction: the updated account and
+    /// the transaction itself. As with [`LedgerStorage::commit`], backends which persist
+    /// changes should override this so that either all or none of the changes are kept.
+    fn commit_admin(&mut self, account: Account, transaction: AdminTransaction) {
+        self.put_admin_transaction(transaction);
+        self.put_account(account);
+    }
+}
+
+/// Reasons why a [`Ledger`] may refuse to apply a transaction. Each variant carries the
+/// ids involved so that callers can decide per-kind whether to skip, log or abort.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum LedgerError {
+    /// Deposit, withdrawal and dispute amounts must be greater than zero
+    NonPositiveAmount { tx_id: u32 },
+    /// Deposits and withdrawals are not allowed against a locked account
+    AccountLocked { client_id: u16, tx_id: u32 },
+    /// No further transactions are allowed against a closed account
+    AccountClosed { client_id: u16, tx_id: u32 },
+    /// Administrative transactions can only be applied to an existing account
+    UnknownAccount { client_id: u16, tx_id: u32 },
+    /// An account can only be closed once it has no available or held funds
+    AccountNotEmpty { client_id: u16, tx_id: u32 },
+    /// Adjustment amounts must be non-zero
+    ZeroAdjustment { tx_id: u32 },
+    /// The withdrawal amount exceeds the available funds
+    InsufficientFunds { client_id: u16, tx_id: u32 },
+    /// A transaction has already been recorded with this id
+    DuplicateTransactionId { tx_id: u32 },
+    /// The transaction referenced by a dispute/resolve/chargeback doesn't exist
+    UnknownTransaction { tx_id: u32 },
+    /// The transaction referenced by a dispute/resolve/chargeback belongs to another client
+    ClientMismatch { client_id: u16, tx_id: u32 },
+    /// The dispute is already open against the transaction
+    AlreadyDisputed { tx_id: u32, dispute_id: Option<u32> },
+    /// There is no open dispute with this id against the transaction
+    NotDisputed { tx_id: u32, dispute_id: Option<u32> },
+    /// The dispute has already been resolved
+    AlreadyResolved { tx_id: u32, dispute_id: Option<u32> },
+    /// The dispute has already been charged back
+    AlreadyChargedBack { tx_id: u32, dispute_id: Option<u32> },
+    /// The total amount disputed against the transaction would exceed its amount
+    DisputeExceedsAmount { tx_id: u32, dispute_id: Option<u32> },
+}
+
+impl LedgerError {
+    /// Short machine-readable identifier for the kind of error, e.g. for reports
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::NonPositiveAmount { .. } => "non_positive_amount",
+            Self::AccountLocked { .. } => "account_locked",
+            Self::AccountClosed { .. } => "account_closed",
+            Self::UnknownAccount { .. } => "unknown_account",
+            Self::AccountNotEmpty { .. } => "account_not_empty",
+            Self::ZeroAdjustment { .. } => "zero_adjustment",
+            Self::InsufficientFunds { .. } => "insufficient_funds",
+            Self::DuplicateTransactionId { .. } => "duplicate_transaction_id",
+            Self::UnknownTransaction { .. } => "unknown_transaction",
+            Self::ClientMismatch { .. } => "client_mismatch",
+            Self::AlreadyDisputed { .. } => "already_disputed",
+            Self::NotDisputed { .. } => "not_disputed",
+            Self::AlreadyResolved { .. } => "already_resolved",
+            Self::AlreadyChargedBack { .. } => "already_charged_back",
+            Self::DisputeExceedsAmount { .. } => "dispute_exceeds_amount",
+        }
+    }
+}
+
+impl std::error::Error for LedgerError {}
+
+impl fmt::Display for LedgerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NonPositiveAmount { tx_id } => {
+                write!(
+                    f,
+                    "Amount must be greater than zero for transaction {}",
+                    tx_id
+                )
+            }
+            Self::AccountLocked { client_id, tx_id } => write!(
+                f,
+                "Account is locked for client id {}, cannot process transaction {}",
+                client_id, tx_id
+            ),
+            Self::AccountClosed { client_id, tx_id } => write!(
+                f,
+                "Account is closed for client id {}, cannot process transaction {}",
+                client_id, tx_id
+            ),
+            Self::UnknownAccount { client_id, tx_id } => write!(
+                f,
+                "No account found for client id {} to process transaction {}",
+                client_id, tx_id
+            ),
+            Self::AccountNotEmpty { client_id, tx_id } => write!(
+                f,
+                "Account for client id {} still holds funds, cannot process close {}",
+                client_id, tx_id
+            ),
+            Self::ZeroAdjustment { tx_id } => {
+                write!(f, "Amount must be non-zero for adjustment {}", tx_id)
+            }
+            Self::InsufficientFunds { client_id, tx_id } => write!(
+                f,
+                "Insufficient funds available for client id {} to process withdrawal {}",
+                client_id, tx_id
+            ),
+            Self::DuplicateTransactionId { tx_id } => {
+                write!(f, "Duplicate transaction id: {}", tx_id)
+            }
+            Self::UnknownTransaction { tx_id } => {
+                write!(f, "No transaction found with id: {}", tx_id)
+            }
+            Self::ClientMismatch { client_id, tx_id } => write!(
+                f,
+                "Transaction with id {} does not belong to client {}",
+                tx_id, client_id
+            ),
+            Self::AlreadyDisputed { tx_id, dispute_id } => {
+                write!(f, "Transaction {} already disputed", tx_id)?;
+                write_dispute_id(f, *dispute_id)
+            }
+            Self::NotDisputed { tx_id, dispute_id } => {
+                write!(f, "Transaction {} not disputed", tx_id)?;
+                write_dispute_id(f, *dispute_id)
+            }
+            Self::AlreadyResolved { tx_id, dispute_id } => {
+                write!(f, "Transaction {} already resolved", tx_id)?;
+                write_dispute_id(f, *dispute_id)
+            }
+            Self::AlreadyChargedBack { tx_id, dispute_id } => {
+                write!(f, "Transaction {} already charged back", tx_id)?;
+                write_dispute_id(f, *dispute_id)
+            }
+            Self::DisputeExceedsAmount { tx_id, dispute_id } => {
+                write!(
+                    f,
+                    "Total disputed amount would exceed the amount of transaction {}",
+                    tx_id
+                )?;
+                write_dispute_id(f, *dispute_id)
+            }
+        }
+    }
+}
+
+fn write_dispute_id(f: &mut fmt::Formatter<'_>, dispute_id: Option<u32>) -> fmt::Result {
+    match dispute_id {
+        Some(dispute_id) => write!(f, " with dispute id {}", dispute_id),
+        None => Ok(()),
+    }
 }
 
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub(crate) struct Account {
     pub client_id: u16,
     pub available: Decimal,
     pub held: Decimal,
     pub is_locked: bool,
+    /// Closed accounts are also locked, but can't be unlocked again
+    pub is_closed: bool,
 }
 
 impl Account {
     pub fn new(client_id: u16) -> Account {
         Account {
-            client_id: client_id,
+            client_id,
             available: Decimal::default(),
             held: Decimal::default(),
             is_locked: false,
+            is_closed: false,
         }
     }
 
@@ -42,20 +247,83 @@ impl Account {
 pub(crate) enum Transaction {
     Standard(StandardTransaction),
     Dispute(DisputeTransaction),
+    Admin(AdminTransaction),
+}
+
+impl Transaction {
+    pub fn client_id(&self) -> u16 {
+        match self {
+            Transaction::Standard(transaction) => transaction.client_id,
+            Transaction::Dispute(transaction) => transaction.client_id,
+            Transaction::Admin(transaction) => transaction.client_id,
+        }
+    }
+
+    pub fn tx_id(&self) -> u32 {
+        match self {
+            Transaction::Standard(transaction) => transaction.tx_id,
+            Transaction::Dispute(transaction) => transaction.tx_id,
+            Transaction::Admin(transaction) => transaction.tx_id,
+        }
+    }
+
+    /// Name of the transaction type, as written in the input
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Transaction::Standard(transaction) => match transaction.tx_type {
+                StandardTransactionType::Deposit => "deposit",
+                StandardTransactionType::Withdrawal => "withdrawal",
+            },
+            Transaction::Dispute(transaction) => match transaction.tx_type {
+                DisputeTransactionType::Dispute => "dispute",
+                DisputeTransactionType::Resolve => "resolve",
+                DisputeTransactionType::Chargeback => "chargeback",
+            },
+            Transaction::Admin(transaction) => match transaction.tx_type {
+                AdminTransactionType::Unlock => "unlock",
+                AdminTransactionType::Freeze => "freeze",
+                AdminTransactionType::Close => "close",
+                AdminTransactionType::Adjustment => "adjustment",
+            },
+        }
+    }
 }
 
 /// 'Standard' transaction here means either a deposit or a withdrawal
-#[derive(Debug)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub(crate) struct StandardTransaction {
     pub tx_type: StandardTransactionType,
     pub client_id: u16,
     pub tx_id: u32,
     pub amount: Decimal,
-    pub dispute_status: Option<DisputeStatus>,
+    pub disputes: Vec<Dispute>,
+}
+
+impl StandardTransaction {
+    pub fn get_dispute(&self, dispute_id: Option<u32>) -> Option<&Dispute> {
+        self.disputes
+            .iter()
+            .find(|dispute| dispute.dispute_id == dispute_id)
+    }
+
+    pub fn get_dispute_mut(&mut self, dispute_id: Option<u32>) -> Option<&mut Dispute> {
+        self.disputes
+            .iter_mut()
+            .find(|dispute| dispute.dispute_id == dispute_id)
+    }
+
+    /// Total amount of all open and charged back disputes against the transaction
+    pub fn disputed_amount(&self) -> Decimal {
+        self.disputes
+            .iter()
+            .filter(|dispute| dispute.status != DisputeStatus::Resolved)
+            .map(|dispute| dispute.amount)
+            .sum()
+    }
 }
 
 /// 'Standard' transaction here means either a deposit or a withdrawal
-#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
 pub(crate) enum StandardTransactionType {
     Deposit,
     Withdrawal,
@@ -66,6 +334,11 @@ pub(crate) struct DisputeTransaction {
     pub tx_type: DisputeTransactionType,
     pub client_id: u16,
     pub tx_id: u32,
+    /// Identifies the dispute when there are several against the same transaction
+    pub dispute_id: Option<u32>,
+    /// Amount being disputed, if only part of the transaction is disputed. Only
+    /// applicable to [`DisputeTransactionType::Dispute`].
+    pub amount: Option<Decimal>,
 }
 
 #[derive(Clone, Copy, Debug, PartialEq)]
@@ -75,8 +348,41 @@ pub(crate) enum DisputeTransactionType {
     Chargeback,
 }
 
-#[derive(Clone, Copy, Debug, PartialEq)]
+/// Operation performed by support staff to remediate an account. Administrative
+/// transactions are recorded, so that re-delivered rows can be recognised.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub(crate) struct AdminTransaction {
+    pub tx_type: AdminTransactionType,
+    pub client_id: u16,
+    pub tx_id: u32,
+    /// Amount added to the available funds, which may be negative. Only applicable to
+    /// [`AdminTransactionType::Adjustment`].
+    pub amount: Option<Decimal>,
+    /// Reason code given by support staff, which is required for adjustments
+    pub reason: Option<String>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub(crate) enum AdminTransactionType {
+    Unlock,
+    Freeze,
+    Close,
+    Adjustment,
+}
+
+/// A single dispute raised against a [`StandardTransaction`]. Disputes are kept after they are
+/// resolved so that re-delivered rows can be recognised, so each dispute id can only be used
+/// once per transaction.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub(crate) struct Dispute {
+    pub dispute_id: Option<u32>,
+    pub amount: Decimal,
+    pub status: DisputeStatus,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
 pub(crate) enum DisputeStatus {
     Unresolved,
+    Resolved,
     Chargeback,
 }
@@ -0,0 +1,367 @@
+use rust_decimal::Decimal;
+
+use crate::ledger::{
+    Account, AdminTransaction, AdminTransactionType, Dispute, DisputeStatus, DisputeTransaction,
+    DisputeTransactionType, Ledger, LedgerError, LedgerStorage, Outcome, StandardTransaction,
+    StandardTransactionType, Transaction,
+};
+
+/// Implements the business rules for deposits, withdrawals, disputes and administrative
+/// transactions on top of any [`LedgerStorage`] backend, so that all backends share identical
+/// semantics.
+///
+/// Each transaction is fully validated before anything is written to storage, and all of
+/// its changes are then written with a single [`LedgerStorage::commit`], so a refused
+/// transaction never changes the ledger.
+pub(crate) struct LedgerEngine<S> {
+    storage: S,
+}
+
+impl<S> LedgerEngine<S> {
+    pub fn new(storage: S) -> LedgerEngine<S> {
+        LedgerEngine { storage }
+    }
+
+    pub fn storage(&self) -> &S {
+        &self.storage
+    }
+
+    pub fn storage_mut(&mut self) -> &mut S {
+        &mut self.storage
+    }
+}
+
+impl<'a, S: LedgerStorage<'a>> LedgerEngine<S> {
+    fn handle_standard(
+        &mut self,
+        transaction: StandardTransaction,
+    ) -> Result<Outcome, LedgerError> {
+        if transaction.amount <= Decimal::ZERO {
+            return Err(LedgerError::NonPositiveAmount {
+                tx_id: transaction.tx_id,
+            });
+        }
+
+        if self.storage.contains_transaction(transaction.tx_id) {
+            // A re-delivered copy of a recorded transaction is acknowledged without being
+            // applied again. Any other transaction with the same id conflicts with it. Note
+            // that backends which don't keep some transactions (e.g. dropped withdrawals)
+            // can't tell the difference, so treat every duplicate id as a conflict.
+            return match self.storage.get_transaction(transaction.tx_id) {
+                Some(recorded)
+                    if recorded.tx_type == transaction.tx_type
+                        && recorded.client_id == transaction.client_id
+                        && recorded.amount == transaction.amount =>
+                {
+                    Ok(Outcome::Replayed)
+                }
+                _ => Err(LedgerError::DuplicateTransactionId {
+                    tx_id: transaction.tx_id,
+                }),
+            };
+        }
+
+        // Administrative transactions share the same ids, so that each id in the audit log
+        // refers to a single transaction
+        if self
+            .storage
+            .get_admin_transaction(transaction.tx_id)
+            .is_some()
+        {
+            return Err(LedgerError::DuplicateTransactionId {
+                tx_id: transaction.tx_id,
+            });
+        }
+
+        // A new account is only stored if the transaction is accepted
+        let mut account = self
+            .storage
+            .get_account(transaction.client_id)
+            .unwrap_or_else(|| Account::new(transaction.client_id));
+
+        if account.is_closed {
+            return Err(LedgerError::AccountClosed {
+                client_id: transaction.client_id,
+                tx_id: transaction.tx_id,
+            });
+        }
+
+        // If the account is locked, we don't allow deposits and withdrawals.
+        if account.is_locked {
+            return Err(LedgerError::AccountLocked {
+                client_id: transaction.client_id,
+                tx_id: transaction.tx_id,
+            });
+        }
+
+        // If it's a withdrawal, ensure there are sufficient funds available
+        if transaction.tx_type == StandardTransactionType::Withdrawal
+            && transaction.amount > account.available
+        {
+            return Err(LedgerError::InsufficientFunds {
+                client_id: transaction.client_id,
+                tx_id: transaction.tx_id,
+            });
+        }
+
+        account.available = match transaction.tx_type {
+            StandardTransactionType::Deposit => account.available + transaction.amount,
+            StandardTransactionType::Withdrawal => account.available - transaction.amount,
+        };
+        self.storage.commit(account, transaction);
+
+        Ok(Outcome::Applied)
+    }
+
+    fn handle_dispute(&mut self, transaction: DisputeTransaction) -> Result<Outcome, LedgerError> {
+        let mut transaction_for_dispute = self.storage.get_transaction(transaction.tx_id).ok_or(
+            LedgerError::UnknownTransaction {
+                tx_id: transaction.tx_id,
+            },
+        )?;
+
+        // The spec doesn't explicitly say this, but it's assumed that specified client_id on the dispute
+        // entry must match the client_id on the transaction being disputed.
+        if transaction.client_id != transaction_for_dispute.client_id {
+            return Err(LedgerError::ClientMismatch {
+                client_id: transaction.client_id,
+                tx_id: transaction.tx_id,
+            });
+        }
+
+        // Note: Disputes are still allowed for locked accounts, so we don't need to check the
+        // 'is_locked' field. The account always exists here because it was created when the
+        // disputed transaction was recorded.
+        let mut account =
+            self.storage
+                .get_account(transaction.client_id)
+                .ok_or(LedgerError::UnknownAccount {
+                    client_id: transaction.client_id,
+                    tx_id: transaction.tx_id,
+                })?;
+
+        let client_id = transaction.client_id;
+        let tx_id = transaction.tx_id;
+        let dispute_id = transaction.dispute_id;
+
+        // Once an account is closed, its disputes can no longer be settled against it. This is
+        // only checked once the row is known not to be a replay, so that re-delivered rows are
+        // still recognised after the account is closed.
+        let refuse_if_closed = |account: &Account| {
+            if account.is_closed {
+                Err(LedgerError::AccountClosed { client_id, tx_id })
+            } else {
+                Ok(())
+            }
+        };
+
+        match transaction.tx_type {
+            DisputeTransactionType::Dispute => {
+                // Several disputes can be open against a transaction at once, so long as they
+                // have different dispute ids. Each dispute id can only be used once, so raising
+                // the same dispute again is a replay if it's for the same amount (or doesn't
+                // give an amount). This includes disputes without an id, so a transaction can
+                // only be disputed again after a resolve by giving a new dispute id.
+                match transaction_for_dispute.get_dispute(dispute_id) {
+                    Some(dispute)
+                        if transaction
+                            .amount
+                            .is_none_or(|amount| amount == dispute.amount) =>
+                    {
+                        return Ok(Outcome::Replayed);
+                    }
+                    Some(Dispute {
+                        status: DisputeStatus::Unresolved,
+                        ..
+                    }) => return Err(LedgerError::AlreadyDisputed { tx_id, dispute_id }),
+                    Some(Dispute {
+                        status: DisputeStatus::Resolved,
+                        ..
+                    }) => return Err(LedgerError::AlreadyResolved { tx_id, dispute_id }),
+                    Some(Dispute {
+                        status: DisputeStatus::Chargeback,
+                        ..
+                    }) => return Err(LedgerError::AlreadyChargedBack { tx_id, dispute_id }),
+                    None => {}
+                }
+
+                refuse_if_closed(&account)?;
+
+                // A dispute may be for only part of the transaction. If no amount is given, the
+                // dispute is for whatever hasn't already been disputed.
+                let undisputed_amount =
+                    transaction_for_dispute.amount - transaction_for_dispute.disputed_amount();
+
+                let amount = match transaction.amount {
+                    Some(amount) if amount <= Decimal::ZERO => {
+                        return Err(LedgerError::NonPositiveAmount { tx_id });
+                    }
+                    Some(amount) => amount,
+                    None => undisputed_amount,
+                };
+
+                if amount <= Decimal::ZERO || amount > undisputed_amount {
+                    return Err(LedgerError::DisputeExceedsAmount { tx_id, dispute_id });
+                }
+
+                transaction_for_dispute.disputes.push(Dispute {
+                    dispute_id,
+                    amount,
+                    status: DisputeStatus::Unresolved,
+                });
+
+                match transaction_for_dispute.tx_type {
+                    StandardTransactionType::Deposit => {
+                        // Move funds from 'available' to 'held'.
+                        // Allow available funds to go into negative here. This represents
+                        // the scenario when funds have already been withdrawn before a dispute has been
+                        // raised. If this were to happen, it is assumed that the entity managing
+                        // the account would be liable for funding any resulting chargeback.
+                        // If a chargeback where to occur, the client account available and total
+                        // funds would remain in deficit.
+                        account.available -= amount;
+                    }
+                    StandardTransactionType::Withdrawal => {
+                        // The withdrawn funds have already left the account, so the client is
+                        // given a provisional credit which is held (i.e. not available) until the
+                        // dispute is resolved or charged back. See README for more info.
+                    }
+                }
+                account.held += amount;
+            }
+            DisputeTransactionType::Resolve => {
+                let dispute = match transaction_for_dispute.get_dispute_mut(dispute_id) {
+                    None => return Err(LedgerError::NotDisputed { tx_id, dispute_id }),
+                    Some(Dispute {
+                        status: DisputeStatus::Resolved,
+                        ..
+                    }) => return Ok(Outcome::Replayed),
+                    Some(Dispute {
+                        status: DisputeStatus::Chargeback,
+                        ..
+                    }) => return Err(LedgerError::AlreadyChargedBack { tx_id, dispute_id }),
+                    Some(dispute) => dispute,
+                };
+
+                refuse_if_closed(&account)?;
+
+                dispute.status = DisputeStatus::Resolved;
+                let amount = dispute.amount;
+
+                // Release the held funds. For a deposit, the funds are restored to available.
+                // For a withdrawal, the withdrawal stands, so the provisional credit is removed.
+                if transaction_for_dispute.tx_type == StandardTransactionType::Deposit {
+                    account.available += amount;
+                }
+                account.held -= amount;
+            }
+            DisputeTransactionType::Chargeback => {
+                let dispute = match transaction_for_dispute.get_dispute_mut(dispute_id) {
+                    None => return Err(LedgerError::NotDisputed { tx_id, dispute_id }),
+                    Some(Dispute {
+                        status: DisputeStatus::Resolved,
+                        ..
+                    }) => return Err(LedgerError::AlreadyResolved { tx_id, dispute_id }),
+                    Some(Dispute {
+                        status: DisputeStatus::Chargeback,
+                        ..
+                    }) => return Ok(Outcome::Replayed),
+                    Some(dispute) => dispute,
+                };
+
+                refuse_if_closed(&account)?;
+
+                dispute.status = DisputeStatus::Chargeback;
+                let amount = dispute.amount;
+
+                // Reverse the disputed amount and lock the account. For a deposit, the held funds
+                // are withdrawn. For a withdrawal, the provisional credit becomes available because
+                // the withdrawn funds are returned to the client. Any other disputes against the
+                // transaction remain open.
+                if transaction_for_dispute.tx_type == StandardTransactionType::Withdrawal {
+                    account.available += amount;
+                }
+                account.held -= amount;
+                account.is_locked = true;
+            }
+        }
+
+        self.storage.commit(account, transaction_for_dispute);
+
+        Ok(Outcome::Applied)
+    }
+
+    fn handle_admin(&mut self, transaction: AdminTransaction) -> Result<Outcome, LedgerError> {
+        let client_id = transaction.client_id;
+        let tx_id = transaction.tx_id;
+
+        if let Some(recorded) = self.storage.get_admin_transaction(tx_id) {
+            // As with deposits and withdrawals, only an identical copy is a replay
+            return if recorded == transaction {
+                Ok(Outcome::Replayed)
+            } else {
+                Err(LedgerError::DuplicateTransactionId { tx_id })
+            };
+        }
+
+        if self.storage.contains_transaction(tx_id) {
+            return Err(LedgerError::DuplicateTransactionId { tx_id });
+        }
+
+        // Administrative transactions never open a new account
+        let mut account = self
+            .storage
+            .get_account(client_id)
+            .ok_or(LedgerError::UnknownAccount { client_id, tx_id })?;
+
+        if account.is_closed {
+            return Err(LedgerError::AccountClosed { client_id, tx_id });
+        }
+
+        match transaction.tx_type {
+            AdminTransactionType::Unlock => account.is_locked = false,
+            AdminTransactionType::Freeze => account.is_locked = true,
+            AdminTransactionType::Close => {
+                // Funds still held for open disputes also prevent the account being closed
+                if account.available != Decimal::ZERO || account.held != Decimal::ZERO {
+                    return Err(LedgerError::AccountNotEmpty { client_id, tx_id });
+                }
+
+                account.is_locked = true;
+                account.is_closed = true;
+            }
+            AdminTransactionType::Adjustment => {
+                // Adjustments may be negative, and are allowed on locked accounts so that
+                // support staff can correct balances before unlocking them.
+                let amount = transaction.amount.unwrap_or_default();
+                if amount == Decimal::ZERO {
+                    return Err(LedgerError::ZeroAdjustment { tx_id });
+                }
+
+                account.available += amount;
+            }
+        }
+
+        self.storage.commit_admin(account, transaction);
+
+        Ok(Outcome::Applied)
+    }
+}
+
+impl<'a, S: LedgerStorage<'a>> Ledger<'a> for LedgerEngine<S> {
+    type AccountsIterator = S::AccountsIterator;
+
+    fn get_accounts(&'a self) -> Self::AccountsIterator {
+        self.storage.get_accounts()
+    }
+
+    fn handle_transaction(&mut self, transaction: Transaction) -> Result<Outcome, LedgerError> {
+        match transaction {
+            Transaction::Standard(standard_transaction) => {
+                self.handle_standard(standard_transaction)
+            }
+            Transaction::Dispute(dispute_transaction) => self.handle_dispute(dispute_transaction),
+            Transaction::Admin(admin_transaction) => self.handle_admin(admin_transaction),
+        }
+    }
+}
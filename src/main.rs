@@ -1,36 +1,756 @@
-use std::{error::Error, io};
+use std::{
+    error::Error,
+    fs::File,
+    io::{self, BufWriter},
+    sync::Arc,
+    thread,
+};
 
-use clap::Parser;
-use ledger::Ledger;
+use clap::{Parser, Subcommand, ValueEnum};
+use csv_transactions::RawFields;
+use history::History;
+use immediate_ledger::{Backend, ImmediateLedger};
+use rejections::Rejection;
 
+mod accounts_output;
+mod amounts;
+mod audit_log;
+mod compact_ledger;
 mod csv_accounts;
 mod csv_transactions;
 mod hashmap_ledger;
+mod history;
+mod http_api;
+mod immediate_ledger;
+mod json_accounts;
+mod json_lines;
+mod jsonl_transactions;
 mod ledger;
+mod ledger_engine;
+mod rebuild;
+mod rejections;
+mod server;
+mod sharded_ledger;
+mod snapshot;
+mod statement;
+mod transactions_input;
+mod wal_ledger;
+
+/// Path which can be given in place of a transactions CSV file to read from stdin
+const STDIN_PATH: &str = "-";
 
 #[derive(Parser, Default, Debug)]
-#[clap(author = "Andrew Harward", about = "Example payments engine")]
+#[clap(
+    author = "Andrew Harward",
+    about = "Example payments engine",
+    subcommand_negates_reqs = true
+)]
 struct Args {
-    #[clap(forbid_empty_values = true, help = "Path to transactions CSV file")]
-    transactions_csv_path: String,
+    #[clap(subcommand)]
+    command: Option<Command>,
+
+    #[clap(
+        required = true,
+        forbid_empty_values = true,
+        help = "Paths to transactions CSV files, processed in order. Use '-' to read from stdin"
+    )]
+    transactions_csv_paths: Vec<String>,
+
+    #[clap(
+        long = "type-alias",
+        global = true,
+        value_name = "ALIAS=TYPE",
+        help = "Accept an alternative name for a transaction type, e.g. withdraw=withdrawal"
+    )]
+    type_aliases: Vec<csv_transactions::TypeAlias>,
+
+    #[clap(
+        long,
+        global = true,
+        value_enum,
+        default_value = "csv",
+        help = "Format of the transactions input files"
+    )]
+    input_format: transactions_input::Format,
+
+    #[clap(
+        long,
+        value_name = "PATH",
+        help = "Path to write rejected transactions and skipped invalid rows to"
+    )]
+    rejections: Option<String>,
+
+    #[clap(
+        long,
+        value_enum,
+        default_value = "csv",
+        help = "Format of the rejections file"
+    )]
+    rejections_format: rejections::Format,
+
+    #[clap(
+        long,
+        global = true,
+        default_value_t = 4,
+        help = "Maximum number of decimal places of input amounts"
+    )]
+    precision: u32,
+
+    #[clap(
+        long,
+        global = true,
+        value_enum,
+        default_value = "reject",
+        help = "What to do with input amounts which have more decimal places than --precision"
+    )]
+    excess_precision: amounts::ExcessPrecision,
+
+    #[clap(
+        long,
+        global = true,
+        value_enum,
+        default_value = "half-even",
+        help = "How to round amounts with excess precision, and output amounts"
+    )]
+    rounding: amounts::Rounding,
+
+    #[clap(
+        long,
+        global = true,
+        value_name = "DECIMAL_PLACES",
+        help = "Write output amounts with a fixed number of decimal places. By default, trailing zeros are omitted"
+    )]
+    output_scale: Option<u32>,
+
+    #[clap(
+        long,
+        global = true,
+        value_enum,
+        default_value = "csv",
+        help = "Format to write the account balances, or statement, to stdout in"
+    )]
+    output_format: accounts_output::Format,
+
+    #[clap(
+        long,
+        global = true,
+        value_enum,
+        default_value = "client",
+        help = "Order in which to write the account balances"
+    )]
+    sort_by: accounts_output::SortKey,
+
+    #[clap(
+        long,
+        default_value_t = 1,
+        help = "Number of threads to process transactions on, with accounts sharded between them by client id"
+    )]
+    shards: usize,
+
+    #[clap(
+        long,
+        global = true,
+        value_name = "PATH",
+        help = "Path to a write-ahead log to recover the ledger from, and to record new transactions in"
+    )]
+    wal: Option<String>,
+
+    #[clap(
+        long,
+        global = true,
+        value_enum,
+        default_value = "hashmap",
+        help = "How to store the ledger in memory"
+    )]
+    storage: StorageKind,
+
+    #[clap(
+        long,
+        global = true,
+        value_name = "PATH",
+        help = "Keep the transaction index in a scratch file at PATH rather than in memory. Requires --storage compact"
+    )]
+    index_file: Option<String>,
+
+    #[clap(
+        long,
+        global = true,
+        help = "Only keep the ids of withdrawals, so they can't be disputed. Requires --storage compact"
+    )]
+    drop_withdrawals: bool,
+
+    #[clap(
+        long,
+        global = true,
+        value_name = "PATH",
+        help = "Path to append an event to for every change made to an account"
+    )]
+    audit_log: Option<String>,
+
+    #[clap(
+        long,
+        global = true,
+        value_name = "PATH",
+        help = "Path to a snapshot of the ledger to restore before processing the input"
+    )]
+    load_state: Option<String>,
+
+    #[clap(
+        long,
+        value_name = "PATH",
+        help = "Path to save a snapshot of the ledger to after processing the input"
+    )]
+    save_state: Option<String>,
+
+    #[clap(
+        long,
+        global = true,
+        value_enum,
+        default_value = "strict",
+        help = "How to handle rows in the input which cannot be parsed"
+    )]
+    error_policy: ErrorPolicy,
+
+    #[clap(
+        long,
+        global = true,
+        default_value_t = 100,
+        help = "Maximum number of invalid rows to skip when using the lenient error policy"
+    )]
+    max_errors: usize,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Accept transactions from TCP connections and apply them to a shared ledger, until
+    /// stopped. Each row is acknowledged with a line of JSON giving the result
+    Serve(ServeArgs),
+    /// Process transactions and write a statement for each client instead of the final
+    /// balances: every transaction processed for the client, in order, with the client's
+    /// balances after each one
+    Statement(StatementArgs),
+    /// Rebuild the account balances from an audit log alone, without processing any
+    /// transactions, and optionally check that they match an accounts CSV
+    Rebuild(RebuildArgs),
+}
+
+#[derive(clap::Args, Debug)]
+struct ServeArgs {
+    #[clap(
+        long,
+        value_name = "ADDR",
+        default_value = "127.0.0.1:7878",
+        help = "Address to listen for connections on. Use port 0 to pick any free port"
+    )]
+    listen: String,
+
+    #[clap(
+        long,
+        value_name = "ADDR",
+        help = "Also serve an HTTP API for submitting transactions and reading balances on ADDR"
+    )]
+    http: Option<String>,
+
+    #[clap(
+        long,
+        help = "Keep every transaction in memory, so that statements can be read from the HTTP API"
+    )]
+    history: bool,
+}
+
+#[derive(clap::Args, Debug)]
+struct StatementArgs {
+    #[clap(
+        long = "client",
+        value_name = "CLIENT",
+        help = "Only write the statement for this client. May be given multiple times"
+    )]
+    client_ids: Vec<u16>,
+
+    #[clap(
+        required = true,
+        forbid_empty_values = true,
+        help = "Paths to transactions CSV files, processed in order. Use '-' to read from stdin"
+    )]
+    transactions_csv_paths: Vec<String>,
+}
+
+#[derive(clap::Args, Debug)]
+struct RebuildArgs {
+    #[clap(
+        forbid_empty_values = true,
+        help = "Path to the audit log to rebuild the accounts from"
+    )]
+    audit_log_path: String,
+
+    #[clap(
+        long,
+        value_name = "PATH",
+        help = "Accounts CSV to compare the rebuilt accounts with, failing if they differ"
+    )]
+    verify: Option<String>,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, ValueEnum)]
+enum StorageKind {
+    /// Keep every transaction in a hash map
+    #[default]
+    Hashmap,
+    /// Keep transactions in a compact index, which uses less than half as much memory
+    Compact,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, ValueEnum)]
+enum ErrorPolicy {
+    /// Abort on the first invalid row
+    #[default]
+    Strict,
+    /// Skip and report invalid rows, but abort once more than `--max-errors` are found
+    Lenient,
+    /// Skip and report all invalid rows
+    SkipInvalid,
+}
+
+impl ErrorPolicy {
+    /// Whether processing can continue after encountering `invalid_rows` invalid rows
+    fn allows(&self, invalid_rows: usize, max_errors: usize) -> bool {
+        match self {
+            ErrorPolicy::Strict => false,
+            ErrorPolicy::Lenient => invalid_rows <= max_errors,
+            ErrorPolicy::SkipInvalid => true,
+        }
+    }
+}
+
+/// Processes transactions either on the main thread, or in parallel on worker threads
+enum Processor {
+    Immediate(immediate_ledger::ImmediateLedger),
+    Sharded(sharded_ledger::ShardedLedger<RowSource>),
+}
+
+/// Where a transaction was read from, so that it can still be reported if it's refused
+/// after being handed off to another thread
+struct RowSource {
+    path_index: usize,
+    fields: RawFields,
+    line: u64,
+}
+
+fn create_processor(args: &Args) -> Result<Processor, Box<dyn Error>> {
+    let uses_snapshots = args.load_state.is_some() || args.save_state.is_some();
+    if uses_snapshots && (args.wal.is_some() || args.shards > 1) {
+        return Err("--load-state and --save-state can't be used with --wal or --shards".into());
+    }
+
+    if args.storage != StorageKind::Compact && (args.index_file.is_some() || args.drop_withdrawals)
+    {
+        return Err("--index-file and --drop-withdrawals require --storage compact".into());
+    }
+
+    if args.storage == StorageKind::Compact && (uses_snapshots || args.wal.is_some()) {
+        return Err(
+            "--storage compact can't be used with --load-state, --save-state or --wal".into(),
+        );
+    }
+
+    // Accounts restored from a snapshot aren't in the audit log, so it couldn't explain
+    // their balances
+    if args.audit_log.is_some() && (args.shards > 1 || args.load_state.is_some()) {
+        return Err("--audit-log can't be used with --shards or --load-state".into());
+    }
+
+    let backend = match (&args.wal, args.shards) {
+        (Some(_), shards) if shards > 1 => {
+            return Err("--wal can't be used with more than one shard".into());
+        }
+        (Some(path), _) => Backend::Durable(ledger_engine::LedgerEngine::new(
+            wal_ledger::WalLedger::open(path, hashmap_ledger::HashMapLedger::new())?,
+        )),
+        (None, shards) if shards > 1 => {
+            if args.storage == StorageKind::Compact {
+                return Err("--storage compact can't be used with more than one shard".into());
+            }
+            return Ok(Processor::Sharded(sharded_ledger::ShardedLedger::new(
+                shards,
+            )));
+        }
+        (None, _) if args.storage == StorageKind::Compact => {
+            let mut storage = match &args.index_file {
+                Some(path) => compact_ledger::CompactLedger::with_index_file(path)?,
+                None => compact_ledger::CompactLedger::new(),
+            };
+            if args.drop_withdrawals {
+                storage = storage.with_dropped_withdrawals();
+            }
+            Backend::Compact(ledger_engine::LedgerEngine::new(storage))
+        }
+        (None, _) => {
+            let storage = match &args.load_state {
+                Some(path) => snapshot::load(path)?,
+                None => hashmap_ledger::HashMapLedger::new(),
+            };
+            Backend::InMemory(ledger_engine::LedgerEngine::new(storage))
+        }
+    };
+
+    let mut ledger = ImmediateLedger::new(backend);
+    if let Some(path) = &args.audit_log {
+        let audit_log = audit_log::AuditLog::open(path)?;
+
+        // The log must contain every change made to the ledger, so it must be started at the
+        // same time as the ledger, and then kept for as long as the ledger is (using --wal).
+        // Events are written after the change is committed to the WAL, so this also catches
+        // a missing last event if the program was killed in between.
+        if let Some(client_id) = audit_log.find_mismatch(ledger.get_accounts()) {
+            return Err(format!(
+                "Audit log {} doesn't match the ledger for client id {}. Use a new audit log \
+                 for a new ledger, and the same audit log whenever a ledger is recovered with \
+                 --wal. If the program was killed, the last event may be missing from the log",
+                path, client_id
+            )
+            .into());
+        }
+
+        ledger = ledger.with_audit_log(audit_log);
+    }
+
+    Ok(Processor::Immediate(ledger))
 }
 
 fn main() -> Result<(), Box<dyn Error>> {
     let args = Args::parse();
 
-    let mut transactions_reader = csv_transactions::Reader::from_path(args.transactions_csv_path)?;
+    let precision = amounts::InputPrecision {
+        decimal_places: args.precision,
+        excess: args.excess_precision,
+        rounding: args.rounding,
+    };
 
-    let mut ledger = hashmap_ledger::HashMapLedger::new();
+    match &args.command {
+        Some(Command::Serve(serve_args)) => return serve(&args, serve_args, precision),
+        Some(Command::Statement(statement_args)) => {
+            return statement(&args, statement_args, precision)
+        }
+        Some(Command::Rebuild(rebuild_args)) => return rebuild(&args, rebuild_args),
+        None => {}
+    }
+
+    let mut rejections_writer = args
+        .rejections
+        .as_ref()
+        .map(|path| rejections::Writer::from_path(path, args.rejections_format))
+        .transpose()?;
+
+    let mut processor = create_processor(&args)?;
+
+    process_inputs(
+        &args,
+        &args.transactions_csv_paths,
+        precision,
+        &mut processor,
+        &mut rejections_writer,
+        None,
+    )?;
 
-    for transaction in transactions_reader.iter() {
-        // Note: Swallow *all* kinds of handling errors and continue - e.g. failed withdrawals,
-        // duplicate transaction ids. Perhaps in future we would want to swallow only
-        // some kinds of errors, and panic on others.
-        let _ = ledger.handle_transaction(transaction?);
+    let sharded_accounts;
+    let mut accounts: Vec<_> = match processor {
+        Processor::Immediate(ref mut ledger) => {
+            ledger.sync()?;
+            if let (Some(path), Backend::InMemory(ledger)) = (&args.save_state, ledger.backend()) {
+                snapshot::save(path, ledger.storage())?;
+            }
+            ledger.get_accounts().collect()
+        }
+        Processor::Sharded(ledger) => {
+            let (accounts, errors) = ledger.finish();
+            for (source, err) in errors {
+                write_sharded_rejection(
+                    &mut rejections_writer,
+                    &args.transactions_csv_paths,
+                    source,
+                    err,
+                )?;
+            }
+
+            sharded_accounts = accounts;
+            sharded_accounts.iter().collect()
+        }
+    };
+
+    if let Some(writer) = rejections_writer.as_mut() {
+        writer.flush()?;
     }
 
-    let accounts_writer = csv_accounts::Writer::from_writer(io::stdout());
-    accounts_writer.write_all(ledger.get_accounts())?;
+    let output_scale = amounts::OutputScale {
+        decimal_places: args.output_scale,
+        rounding: args.rounding,
+    };
+    let accounts_writer =
+        accounts_output::Writer::from_writer(io::stdout(), args.output_format, output_scale);
+    args.sort_by.sort(&mut accounts);
+    accounts_writer.write_all(accounts.into_iter())?;
+
+    Ok(())
+}
+
+/// Reads and processes the transactions in each of the paths in turn. If a history is given,
+/// the result of each transaction is recorded in it.
+fn process_inputs(
+    args: &Args,
+    paths: &[String],
+    precision: amounts::InputPrecision,
+    processor: &mut Processor,
+    rejections_writer: &mut Option<rejections::Writer<BufWriter<File>>>,
+    mut history: Option<&mut History>,
+) -> Result<(), Box<dyn Error>> {
+    let mut invalid_rows = 0;
+
+    for (path_index, path) in paths.iter().enumerate() {
+        let input: Box<dyn io::Read> = if path == STDIN_PATH {
+            Box::new(io::stdin())
+        } else {
+            Box::new(File::open(path)?)
+        };
+
+        let type_aliases: csv_transactions::TypeAliases =
+            args.type_aliases.iter().cloned().collect();
+
+        let mut reader = transactions_input::Reader::from_reader(input, args.input_format)
+            .with_type_aliases(type_aliases)
+            .with_precision(precision);
+
+        for row in reader.iter()? {
+            let transaction = match row.transaction {
+                Ok(transaction) => transaction,
+                Err(err) => {
+                    invalid_rows += 1;
+                    if err.is_fatal() || !args.error_policy.allows(invalid_rows, args.max_errors) {
+                        return Err(err.into());
+                    }
+
+                    if let Some(writer) = rejections_writer.as_mut() {
+                        writer.write(&Rejection::new(
+                            path,
+                            &row.fields,
+                            row.line,
+                            err.kind(),
+                            err.to_string(),
+                        ))?;
+                    }
+                    continue;
+                }
+            };
+
+            // Note: Swallow *all* kinds of ledger errors and continue - e.g. failed withdrawals,
+            // duplicate transaction ids. [`ledger::LedgerError`] is typed, so callers can match
+            // on it to skip some kinds of errors and abort on others.
+            let result = match processor {
+                Processor::Immediate(ledger) => {
+                    let client_id = transaction.client_id();
+                    let summary = history
+                        .as_deref()
+                        .and_then(|history| history.summarize(&transaction));
+
+                    let result = ledger.handle_transaction(transaction)?;
+
+                    if let (Some(history), Some(summary)) = (history.as_deref_mut(), summary) {
+                        let account = ledger.get_account(client_id);
+                        history.record(summary, path, row.line, result, account);
+                    }
+                    result
+                }
+                Processor::Sharded(ledger) => {
+                    let source = RowSource {
+                        path_index,
+                        fields: row.fields,
+                        line: row.line,
+                    };
+                    ledger.handle_transaction(transaction, source);
+
+                    for (source, err) in ledger.errors() {
+                        write_sharded_rejection(rejections_writer, paths, source, err)?;
+                    }
+                    continue;
+                }
+            };
+
+            if let Err(err) = result {
+                if let Some(writer) = rejections_writer.as_mut() {
+                    writer.write(&Rejection::new(
+                        path,
+                        &row.fields,
+                        row.line,
+                        err.kind(),
+                        err.to_string(),
+                    ))?;
+                }
+            }
+        }
+    }
 
     Ok(())
 }
+
+/// Processes the input like the main command, but writes a statement for each client
+/// instead of the final balances
+fn statement(
+    args: &Args,
+    statement_args: &StatementArgs,
+    precision: amounts::InputPrecision,
+) -> Result<(), Box<dyn Error>> {
+    // Statements are read-only, so they mustn't append the input to a write-ahead log or
+    // audit log
+    if args.shards > 1
+        || args.wal.is_some()
+        || args.save_state.is_some()
+        || args.rejections.is_some()
+        || args.audit_log.is_some()
+    {
+        return Err(
+            "statement can't be used with --shards, --wal, --save-state, --rejections or \
+             --audit-log"
+                .into(),
+        );
+    }
+
+    let mut processor = create_processor(args)?;
+
+    let mut history = History::new();
+    if !statement_args.client_ids.is_empty() {
+        history = history.with_clients(statement_args.client_ids.iter().copied());
+    }
+
+    process_inputs(
+        args,
+        &statement_args.transactions_csv_paths,
+        precision,
+        &mut processor,
+        &mut None,
+        Some(&mut history),
+    )?;
+
+    let output_scale = amounts::OutputScale {
+        decimal_places: args.output_scale,
+        rounding: args.rounding,
+    };
+    let client_ids = history.client_ids();
+    statement::Writer::from_writer(io::stdout(), args.output_format, output_scale).write_all(
+        client_ids
+            .iter()
+            .flat_map(|&client_id| history.get_entries(client_id)),
+    )?;
+
+    Ok(())
+}
+
+/// Writes the accounts rebuilt from an audit log, after checking them against the accounts
+/// CSV to verify, if any
+fn rebuild(args: &Args, rebuild_args: &RebuildArgs) -> Result<(), Box<dyn Error>> {
+    let log = File::open(&rebuild_args.audit_log_path)?;
+    let accounts = rebuild::rebuild(io::BufReader::new(log))?;
+
+    let output_scale = amounts::OutputScale {
+        decimal_places: args.output_scale,
+        rounding: args.rounding,
+    };
+
+    if let Some(path) = &rebuild_args.verify {
+        let expected = csv_accounts::read_all(File::open(path)?)?;
+
+        let mismatches = rebuild::verify(&accounts, &expected, &output_scale);
+        if !mismatches.is_empty() {
+            for mismatch in &mismatches {
+                eprintln!("{}", mismatch);
+            }
+            return Err(format!(
+                "{} account(s) rebuilt from the audit log don't match {}",
+                mismatches.len(),
+                path
+            )
+            .into());
+        }
+    }
+
+    let mut accounts: Vec<_> = accounts.iter().collect();
+    args.sort_by.sort(&mut accounts);
+    accounts_output::Writer::from_writer(io::stdout(), args.output_format, output_scale)
+        .write_all(accounts.into_iter())?;
+
+    Ok(())
+}
+
+/// Runs the TCP server until the process is stopped
+fn serve(
+    args: &Args,
+    serve_args: &ServeArgs,
+    precision: amounts::InputPrecision,
+) -> Result<(), Box<dyn Error>> {
+    if args.shards > 1 || args.save_state.is_some() || args.rejections.is_some() {
+        return Err("serve can't be used with --shards, --save-state or --rejections".into());
+    }
+
+    if serve_args.history && serve_args.http.is_none() {
+        return Err("--history requires --http".into());
+    }
+
+    let ledger = match create_processor(args)? {
+        Processor::Immediate(ledger) => ledger,
+        Processor::Sharded(_) => unreachable!("sharding is disabled above"),
+    };
+
+    let input = server::InputOptions {
+        format: args.input_format,
+        type_aliases: args.type_aliases.iter().cloned().collect(),
+        precision,
+    };
+
+    let mut ledger = server::SharedLedger::new(ledger);
+    if serve_args.history {
+        ledger = ledger.with_history();
+    }
+    let ledger = Arc::new(ledger);
+
+    let http_api = serve_args
+        .http
+        .as_ref()
+        .map(|addr| {
+            let output_scale = amounts::OutputScale {
+                decimal_places: args.output_scale,
+                rounding: args.rounding,
+            };
+            http_api::HttpApi::bind(addr, Arc::clone(&ledger), input.clone(), output_scale)
+        })
+        .transpose()?;
+
+    let server = server::Server::bind(&serve_args.listen, ledger, input)?;
+    eprintln!("Listening on {}", server.local_addr()?);
+
+    if let Some(http_api) = http_api {
+        if let Some(addr) = http_api.local_addr() {
+            eprintln!("HTTP API listening on {}", addr);
+        }
+        thread::spawn(move || http_api.run());
+    }
+
+    server.run();
+
+    Ok(())
+}
+
+fn write_sharded_rejection<W: io::Write>(
+    rejections_writer: &mut Option<rejections::Writer<W>>,
+    paths: &[String],
+    source: RowSource,
+    err: ledger::LedgerError,
+) -> Result<(), rejections::Error> {
+    match rejections_writer {
+        Some(writer) => writer.write(&Rejection::new(
+            &paths[source.path_index],
+            &source.fields,
+            source.line,
+            err.kind(),
+            err.to_string(),
+        )),
+        None => Ok(()),
+    }
+}
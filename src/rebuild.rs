@@ -0,0 +1,203 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    io::{self, BufRead},
+};
+
+use crate::{
+    amounts::OutputScale,
+    audit_log::AuditEvent,
+    csv_accounts::AccountRow,
+    json_lines::{self, JsonLines},
+    ledger::Account,
+};
+
+/// Reconstructs every account purely from the events in an audit log, without applying any
+/// business rules. Each event's changes are applied to the account, and the result must
+/// match the balances recorded in the event, so the log is checked to be consistent as well
+/// as complete.
+///
+/// Returns the accounts ordered by client id. As when the log is opened for writing, an
+/// incomplete event at the end of the log is ignored.
+pub(crate) fn rebuild<R: BufRead>(reader: R) -> Result<Vec<Account>, Error> {
+    let mut accounts: HashMap<u16, Account> = HashMap::new();
+
+    for event in JsonLines::new(reader) {
+        let (line, event): (u64, AuditEvent) = event?;
+
+        if event.seq != line {
+            return Err(Error::OutOfSequence {
+                line,
+                seq: event.seq,
+            });
+        }
+
+        let account = accounts
+            .entry(event.client)
+            .or_insert_with(|| Account::new(event.client));
+
+        account.available += event.available_delta;
+        account.held += event.held_delta;
+        account.is_locked = event.locked;
+        account.is_closed = event.closed;
+
+        if account.available != event.available
+            || account.held != event.held
+            || account.total() != event.total
+        {
+            return Err(Error::Inconsistent {
+                seq: event.seq,
+                client_id: event.client,
+            });
+        }
+    }
+
+    let mut accounts: Vec<Account> = accounts.into_values().collect();
+    accounts.sort_by_key(|account| account.client_id);
+    Ok(accounts)
+}
+
+/// Compares rebuilt accounts with accounts read from an accounts CSV, returning every
+/// difference. The rebuilt balances are first written with `scale`, so it must match the
+/// `--output-scale` the CSV was written with.
+pub(crate) fn verify(
+    accounts: &[Account],
+    expected: &[AccountRow],
+    scale: &OutputScale,
+) -> Vec<Mismatch> {
+    let mut expected_by_client_id: HashMap<u16, &AccountRow> =
+        expected.iter().map(|row| (row.client, row)).collect();
+
+    let mut mismatches = Vec::new();
+
+    for account in accounts {
+        let actual = AccountRow {
+            client: account.client_id,
+            available: scale.apply(account.available),
+            held: scale.apply(account.held),
+            total: scale.apply(account.total()),
+            locked: account.is_locked,
+        };
+
+        match expected_by_client_id.remove(&account.client_id) {
+            // Decimals compare equal regardless of trailing zeros
+            Some(expected) if *expected == actual => {}
+            Some(expected) => mismatches.push(Mismatch::Balances {
+                expected: expected.clone(),
+                actual,
+            }),
+            None => mismatches.push(Mismatch::Unexpected {
+                client_id: account.client_id,
+            }),
+        }
+    }
+
+    let mut missing: Vec<u16> = expected_by_client_id.into_keys().collect();
+    missing.sort_unstable();
+    mismatches.extend(
+        missing
+            .into_iter()
+            .map(|client_id| Mismatch::Missing { client_id }),
+    );
+
+    mismatches
+}
+
+/// A difference between the rebuilt accounts and the expected accounts
+#[derive(Debug, PartialEq)]
+pub(crate) enum Mismatch {
+    /// The account's balances differ
+    Balances {
+        expected: AccountRow,
+        actual: AccountRow,
+    },
+    /// The account is in the audit log, but not in the expected accounts
+    Unexpected { client_id: u16 },
+    /// The account is in the expected accounts, but not in the audit log
+    Missing { client_id: u16 },
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Balances { expected, actual } => write!(
+                f,
+                "Account for client id {} differs: expected {}, rebuilt {}",
+                expected.client,
+                format_balances(expected),
+                format_balances(actual)
+            ),
+            Self::Unexpected { client_id } => write!(
+                f,
+                "Account for client id {} was rebuilt, but isn't in the expected accounts",
+                client_id
+            ),
+            Self::Missing { client_id } => write!(
+                f,
+                "Account for client id {} is expected, but has no events in the audit log",
+                client_id
+            ),
+        }
+    }
+}
+
+fn format_balances(row: &AccountRow) -> String {
+    format!(
+        "available {}, held {}, total {}, locked {}",
+        row.available.normalize(),
+        row.held.normalize(),
+        row.total.normalize(),
+        row.locked
+    )
+}
+
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    Corrupt {
+        line: u64,
+        err: serde_json::Error,
+    },
+    /// Events must be numbered consecutively from 1, otherwise some are missing
+    OutOfSequence {
+        line: u64,
+        seq: u64,
+    },
+    /// Applying the event's changes doesn't give the balances it records
+    Inconsistent {
+        seq: u64,
+        client_id: u16,
+    },
+}
+
+impl From<json_lines::Error> for Error {
+    fn from(err: json_lines::Error) -> Error {
+        match err {
+            json_lines::Error::Io(err) => Error::Io(err),
+            json_lines::Error::Corrupt { line, err } => Error::Corrupt { line, err },
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(err) => write!(f, "Failed to read audit log: {}", err),
+            Self::Corrupt { line, err } => {
+                write!(f, "Audit log is corrupt at line {}: {}", line, err)
+            }
+            Self::OutOfSequence { line, seq } => write!(
+                f,
+                "Audit log is out of sequence at line {}: expected event {}, found event {}",
+                line, line, seq
+            ),
+            Self::Inconsistent { seq, client_id } => write!(
+                f,
+                "Audit log event {} doesn't match the rebuilt balances for client id {}",
+                seq, client_id
+            ),
+        }
+    }
+}
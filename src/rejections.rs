@@ -0,0 +1,112 @@
+use std::fs::File;
+use std::io::BufWriter;
+use std::{fmt, io, path::Path};
+
+use clap::ValueEnum;
+use serde::Serialize;
+
+use crate::csv_transactions::RawFields;
+
+/// A transaction that was refused, along with the reason it was refused
+#[derive(Debug, Serialize)]
+pub(crate) struct Rejection<'a> {
+    /// Path of the input the transaction was read from
+    pub source: &'a str,
+    #[serde(rename = "type")]
+    pub tx_type: &'a str,
+    pub client: &'a str,
+    pub tx: &'a str,
+    pub amount: &'a str,
+    pub line: u64,
+    pub reason: &'static str,
+    pub message: String,
+}
+
+impl<'a> Rejection<'a> {
+    pub fn new(
+        source: &'a str,
+        fields: &'a RawFields,
+        line: u64,
+        reason: &'static str,
+        message: String,
+    ) -> Self {
+        Rejection {
+            source,
+            tx_type: &fields.tx_type,
+            client: &fields.client,
+            tx: &fields.tx,
+            amount: &fields.amount,
+            line,
+            reason,
+            message,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, ValueEnum)]
+pub(crate) enum Format {
+    #[default]
+    Csv,
+    Jsonl,
+}
+
+pub(crate) enum Writer<W: io::Write> {
+    Csv(Box<csv::Writer<W>>),
+    Jsonl(W),
+}
+
+impl Writer<BufWriter<File>> {
+    pub fn from_path<P: AsRef<Path>>(
+        path: P,
+        format: Format,
+    ) -> Result<Writer<BufWriter<File>>, Error> {
+        let file = File::create(path).map_err(Error::Io)?;
+        Ok(Writer::from_writer(BufWriter::new(file), format))
+    }
+}
+
+impl<W: io::Write> Writer<W> {
+    pub fn from_writer(writer: W, format: Format) -> Writer<W> {
+        match format {
+            Format::Csv => Writer::Csv(Box::new(csv::Writer::from_writer(writer))),
+            Format::Jsonl => Writer::Jsonl(writer),
+        }
+    }
+
+    pub fn write(&mut self, rejection: &Rejection) -> Result<(), Error> {
+        match self {
+            Writer::Csv(writer) => writer.serialize(rejection).map_err(Error::Csv),
+            Writer::Jsonl(writer) => {
+                serde_json::to_writer(&mut *writer, rejection).map_err(Error::Json)?;
+                writer.write_all(b"\n").map_err(Error::Io)
+            }
+        }
+    }
+
+    pub fn flush(&mut self) -> Result<(), Error> {
+        match self {
+            Writer::Csv(writer) => writer.flush(),
+            Writer::Jsonl(writer) => writer.flush(),
+        }
+        .map_err(Error::Io)
+    }
+}
+
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    Csv(csv::Error),
+    Json(serde_json::Error),
+}
+
+impl std::error::Error for Error {}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(err) => write!(f, "Failed to write rejections: {}", err),
+            Self::Csv(err) => write!(f, "Failed to write rejections CSV: {}", err),
+            Self::Json(err) => write!(f, "Failed to write rejections JSON: {}", err),
+        }
+    }
+}
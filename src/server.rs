@@ -0,0 +1,259 @@
+use std::{
+    io::{self, BufWriter, Write},
+    net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs},
+    sync::{Arc, Mutex, MutexGuard},
+    thread,
+};
+
+use serde::Serialize;
+
+use crate::{
+    amounts::InputPrecision,
+    csv_transactions::{self, TransactionRow, TypeAliases},
+    history::History,
+    immediate_ledger::ImmediateLedger,
+    ledger::Outcome,
+    transactions_input,
+};
+
+/// How transactions are read from each connection
+#[derive(Clone, Debug, Default)]
+pub(crate) struct InputOptions {
+    pub format: transactions_input::Format,
+    pub type_aliases: TypeAliases,
+    pub precision: InputPrecision,
+}
+
+/// Accepts transactions from any number of TCP connections and applies them to a single
+/// shared ledger. Each connection sends transactions in the same format as an input file
+/// (so CSV connections start with a header row), and receives an [`Acknowledgement`] for
+/// every row, in order, as soon as it has been processed.
+///
+/// Transactions from different connections are interleaved in the order they arrive, but
+/// the transactions from each connection are always processed in the order they were sent.
+pub(crate) struct Server {
+    listener: TcpListener,
+    ledger: Arc<SharedLedger>,
+    input: InputOptions,
+}
+
+impl Server {
+    pub fn bind<A: ToSocketAddrs>(
+        addr: A,
+        ledger: Arc<SharedLedger>,
+        input: InputOptions,
+    ) -> io::Result<Server> {
+        Ok(Server {
+            listener: TcpListener::bind(addr)?,
+            ledger,
+            input,
+        })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    /// Accepts connections until the process is stopped, handling each one on its own thread
+    pub fn run(self) {
+        for stream in self.listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(err) => {
+                    eprintln!("Failed to accept connection: {}", err);
+                    continue;
+                }
+            };
+
+            let ledger = Arc::clone(&self.ledger);
+            let input = self.input.clone();
+            thread::spawn(move || {
+                let peer = stream
+                    .peer_addr()
+                    .map_or_else(|_| "unknown peer".to_string(), |addr| addr.to_string());
+
+                if let Err(err) = handle_connection(stream, &ledger, input, &peer) {
+                    eprintln!("Connection from {} failed: {}", peer, err);
+                }
+            });
+        }
+    }
+}
+
+/// Result of processing a single row received from a connection, which is written back to
+/// the connection as one line of JSON
+#[derive(Debug, Serialize)]
+pub(crate) struct Acknowledgement {
+    /// Line number of the row within the data sent on the connection
+    pub line: u64,
+    #[serde(rename = "type")]
+    pub tx_type: String,
+    pub client: String,
+    pub tx: String,
+    pub result: AcknowledgementResult,
+    /// Machine-readable reason the row was refused, matching the rejections file
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum AcknowledgementResult {
+    /// The transaction was applied to the ledger
+    Accepted,
+    /// The transaction had already been applied, so it was acknowledged without changing the
+    /// ledger
+    Replayed,
+    /// The transaction was valid, but was refused by the ledger, e.g. insufficient funds
+    Rejected,
+    /// The row couldn't be parsed as a transaction
+    Invalid,
+    /// The ledger's storage has failed, so the transaction couldn't be processed
+    Failed,
+}
+
+/// Ledger shared between every connection, which processes one transaction at a time
+pub(crate) struct SharedLedger(Mutex<Shared>);
+
+struct Shared {
+    ledger: ImmediateLedger,
+    /// Set once the ledger's storage has failed, after which every transaction is refused
+    failure: Option<String>,
+    history: Option<History>,
+}
+
+impl SharedLedger {
+    pub fn new(ledger: ImmediateLedger) -> SharedLedger {
+        SharedLedger(Mutex::new(Shared {
+            ledger,
+            failure: None,
+            history: None,
+        }))
+    }
+
+    /// Records every transaction in a [`History`], so that statements can be read
+    pub fn with_history(mut self) -> SharedLedger {
+        self.0.get_mut().expect("ledger lock poisoned").history = Some(History::new());
+        self
+    }
+
+    /// Processes a row read from a connection, identified by `source`. Returns an error if
+    /// the row couldn't be read at all, in which case nothing more can be read from the
+    /// connection.
+    pub fn handle_row(
+        &self,
+        row: TransactionRow,
+        source: &str,
+    ) -> Result<Acknowledgement, csv_transactions::Error> {
+        let (result, reason, message) = match row.transaction {
+            Err(err) if err.is_fatal() => return Err(err),
+            Err(err) => (
+                AcknowledgementResult::Invalid,
+                Some(err.kind()),
+                Some(err.to_string()),
+            ),
+            Ok(transaction) => {
+                let mut guard = self.lock();
+                let shared = &mut *guard;
+
+                let client_id = transaction.client_id();
+                let summary = shared
+                    .history
+                    .as_ref()
+                    .and_then(|history| history.summarize(&transaction));
+
+                match &shared.failure {
+                    Some(failure) => (
+                        AcknowledgementResult::Failed,
+                        Some("storage_failure"),
+                        Some(failure.clone()),
+                    ),
+                    None => match shared.ledger.handle_transaction(transaction) {
+                        Ok(result) => {
+                            if let (Some(history), Some(summary)) = (&mut shared.history, summary) {
+                                let account = shared.ledger.get_account(client_id);
+                                history.record(summary, source, row.line, result, account);
+                            }
+
+                            match result {
+                                Ok(Outcome::Applied) => {
+                                    (AcknowledgementResult::Accepted, None, None)
+                                }
+                                Ok(Outcome::Replayed) => {
+                                    (AcknowledgementResult::Replayed, None, None)
+                                }
+                                Err(err) => (
+                                    AcknowledgementResult::Rejected,
+                                    Some(err.kind()),
+                                    Some(err.to_string()),
+                                ),
+                            }
+                        }
+                        Err(err) => {
+                            eprintln!("Ledger storage failed: {}", err);
+                            shared.failure = Some(err.to_string());
+                            (
+                                AcknowledgementResult::Failed,
+                                Some("storage_failure"),
+                                Some(err.to_string()),
+                            )
+                        }
+                    },
+                }
+            }
+        };
+
+        Ok(Acknowledgement {
+            line: row.line,
+            tx_type: row.fields.tx_type,
+            client: row.fields.client,
+            tx: row.fields.tx,
+            result,
+            reason,
+            message,
+        })
+    }
+
+    /// Reads from the ledger without any transactions being processed in the meantime
+    pub fn read<T>(&self, f: impl FnOnce(&ImmediateLedger) -> T) -> T {
+        f(&self.lock().ledger)
+    }
+
+    /// Reads from the history, if transactions are being recorded
+    pub fn read_history<T>(&self, f: impl FnOnce(&History) -> T) -> Option<T> {
+        self.lock().history.as_ref().map(f)
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Shared> {
+        self.0.lock().expect("ledger lock poisoned")
+    }
+}
+
+fn handle_connection(
+    stream: TcpStream,
+    ledger: &SharedLedger,
+    input: InputOptions,
+    peer: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut acks = BufWriter::new(stream.try_clone()?);
+
+    let mut reader = transactions_input::Reader::from_reader(stream, input.format)
+        .with_type_aliases(input.type_aliases)
+        .with_precision(input.precision);
+
+    for row in reader.iter()? {
+        write_ack(&mut acks, &ledger.handle_row(row, peer)?)?;
+    }
+
+    Ok(())
+}
+
+/// Writes the acknowledgement and flushes it immediately, because the client may be waiting
+/// for it before sending the next transaction
+fn write_ack<W: Write>(writer: &mut W, ack: &Acknowledgement) -> io::Result<()> {
+    serde_json::to_writer(&mut *writer, ack)?;
+    writer.write_all(b"\n")?;
+    writer.flush()
+}
@@ -0,0 +1,296 @@
+use std::{
+    collections::{hash_map::Entry, HashMap},
+    panic,
+    sync::mpsc::{self, Receiver, RecvTimeoutError, SyncSender},
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use rust_decimal::Decimal;
+
+use crate::{
+    hashmap_ledger::HashMapLedger,
+    ledger::{Account, Ledger, LedgerError, Transaction},
+    ledger_engine::LedgerEngine,
+};
+
+/// Transactions are sent to the workers in batches, because sending each transaction
+/// individually costs more than processing it.
+const BATCH_SIZE: usize = 1024;
+
+/// Number of batches which can be queued for each shard before
+/// [`ShardedLedger::handle_transaction`] blocks, so that memory usage stays bounded when the
+/// input is read faster than it can be processed.
+const SHARD_QUEUE_CAPACITY: usize = 16;
+
+/// How often [`ShardedLedger`] checks that a worker is still running while waiting for it to
+/// report the outcome of a transaction
+const OUTCOME_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Ledger which processes transactions in parallel on a pool of worker threads. Accounts are
+/// sharded by client id, and each worker owns the ledger for a single shard. All transactions
+/// for a client are processed by the same worker in the order they were submitted.
+///
+/// Because transactions are processed asynchronously, this doesn't implement [`Ledger`].
+/// Instead, each transaction is submitted with a `tag` which is returned alongside any
+/// [`LedgerError`] so that the caller can tell which transaction was refused.
+///
+/// Shards don't share any state, so the client using each transaction id is kept on the
+/// submitting thread. Transactions reusing another client's transaction id, and disputes for
+/// unknown or another client's transactions, are refused before they reach a shard. An id only
+/// belongs to a client once its shard has accepted a transaction with it, so shards report
+/// the outcome of every transaction which could take an id, and another client's use of an id
+/// waits for those outcomes. This gives the same results as a single ledger.
+pub(crate) struct ShardedLedger<T> {
+    senders: Vec<SyncSender<Vec<Submission<T>>>>,
+    batches: Vec<Vec<Submission<T>>>,
+    workers: Vec<JoinHandle<Vec<Account>>>,
+    errors: Receiver<(T, LedgerError)>,
+    /// Used to report transactions which are refused before reaching a shard
+    errors_sender: mpsc::Sender<(T, LedgerError)>,
+    /// Outcomes reported by the shards for each batch, as `(tx_id, accepted)`
+    outcomes: Receiver<Vec<(u32, bool)>>,
+    reservations_by_tx_id: HashMap<u32, Reservation>,
+}
+
+/// A transaction queued for a shard, along with its tag and whether the shard must report
+/// its outcome
+type Submission<T> = (Transaction, T, bool);
+
+/// The client using a transaction id
+struct Reservation {
+    client_id: u16,
+    /// Whether the client's shard has accepted a transaction with the id
+    accepted: bool,
+    /// Number of the client's transactions with the id whose outcome hasn't been reported yet
+    pending: u32,
+}
+
+impl<T: Send + 'static> ShardedLedger<T> {
+    pub fn new(shards: usize) -> ShardedLedger<T> {
+        let (errors_sender, errors) = mpsc::channel();
+        let (outcomes_sender, outcomes) = mpsc::channel();
+
+        let shards = shards.max(1);
+        let (senders, workers) = (0..shards)
+            .map(|_| {
+                let (sender, receiver) = mpsc::sync_channel(SHARD_QUEUE_CAPACITY);
+                let errors_sender = errors_sender.clone();
+                let outcomes_sender = outcomes_sender.clone();
+                let worker =
+                    thread::spawn(move || run_shard(receiver, errors_sender, outcomes_sender));
+                (sender, worker)
+            })
+            .unzip();
+
+        ShardedLedger {
+            senders,
+            batches: (0..shards)
+                .map(|_| Vec::with_capacity(BATCH_SIZE))
+                .collect(),
+            workers,
+            errors,
+            errors_sender,
+            outcomes,
+            reservations_by_tx_id: HashMap::new(),
+        }
+    }
+
+    /// Queues the transaction to be processed by the shard which owns the client's account,
+    /// unless it conflicts with a transaction submitted for another client
+    pub fn handle_transaction(&mut self, transaction: Transaction, tag: T) {
+        let report_outcome = match self.check_transaction_id(&transaction) {
+            Ok(report_outcome) => report_outcome,
+            Err(err) => {
+                // The receiver is only dropped along with the ledger
+                let _ = self.errors_sender.send((tag, err));
+                return;
+            }
+        };
+
+        let shard = self.shard_for(transaction.client_id());
+
+        self.batches[shard].push((transaction, tag, report_outcome));
+        if self.batches[shard].len() >= BATCH_SIZE {
+            self.send_batch(shard);
+        }
+    }
+
+    fn shard_for(&self, client_id: u16) -> usize {
+        // Client ids are assigned sequentially in practice, so they are already evenly
+        // distributed between shards without hashing.
+        usize::from(client_id) % self.senders.len()
+    }
+
+    /// Checks the transaction id against every shard, as a single ledger would. Transactions
+    /// for the client using the id are left for its shard to check. Returns whether the shard
+    /// must report the outcome of the transaction, because it could take the id.
+    fn check_transaction_id(&mut self, transaction: &Transaction) -> Result<bool, LedgerError> {
+        let tx_id = transaction.tx_id();
+        let client_id = transaction.client_id();
+
+        match transaction {
+            // Non-positive amounts are refused by the shard before the id is checked
+            Transaction::Standard(transaction) if transaction.amount <= Decimal::ZERO => Ok(false),
+            Transaction::Standard(_) | Transaction::Admin(_) => {
+                self.wait_for_reservation(tx_id, client_id);
+
+                match self.reservations_by_tx_id.entry(tx_id) {
+                    Entry::Vacant(entry) => {
+                        entry.insert(Reservation {
+                            client_id,
+                            accepted: false,
+                            pending: 1,
+                        });
+                        Ok(true)
+                    }
+                    Entry::Occupied(entry) if entry.get().client_id != client_id => {
+                        Err(LedgerError::DuplicateTransactionId { tx_id })
+                    }
+                    Entry::Occupied(mut entry) => {
+                        let reservation = entry.get_mut();
+                        if reservation.accepted {
+                            Ok(false)
+                        } else {
+                            reservation.pending += 1;
+                            Ok(true)
+                        }
+                    }
+                }
+            }
+            Transaction::Dispute(_) => {
+                self.wait_for_reservation(tx_id, client_id);
+
+                match self.reservations_by_tx_id.get(&tx_id) {
+                    None => Err(LedgerError::UnknownTransaction { tx_id }),
+                    Some(reservation) if reservation.client_id != client_id => {
+                        Err(LedgerError::ClientMismatch { client_id, tx_id })
+                    }
+                    Some(_) => Ok(false),
+                }
+            }
+        }
+    }
+
+    /// Waits until the id is either free or taken, if it's only reserved by another client
+    fn wait_for_reservation(&mut self, tx_id: u32, client_id: u16) {
+        while let Some(reservation) = self.reservations_by_tx_id.get(&tx_id) {
+            if reservation.client_id == client_id || reservation.accepted {
+                return;
+            }
+
+            // The shard can only report the outcome once it has been sent the transaction
+            let shard = self.shard_for(reservation.client_id);
+            if !self.batches[shard].is_empty() {
+                self.send_batch(shard);
+            } else if !self.wait_for_outcomes(shard) {
+                return;
+            }
+        }
+    }
+
+    /// Waits for the next outcomes reported by any shard. Returns `false` if the given shard's
+    /// worker has stopped, since it will never report any more outcomes.
+    fn wait_for_outcomes(&mut self, shard: usize) -> bool {
+        loop {
+            match self.outcomes.recv_timeout(OUTCOME_POLL_INTERVAL) {
+                Ok(outcomes) => {
+                    self.record_outcomes(outcomes);
+                    return true;
+                }
+                Err(RecvTimeoutError::Timeout) if !self.workers[shard].is_finished() => {}
+                Err(_) => return false,
+            }
+        }
+    }
+
+    fn record_outcomes(&mut self, outcomes: Vec<(u32, bool)>) {
+        for (tx_id, accepted) in outcomes {
+            if let Entry::Occupied(mut entry) = self.reservations_by_tx_id.entry(tx_id) {
+                let reservation = entry.get_mut();
+                reservation.pending -= 1;
+                reservation.accepted |= accepted;
+
+                // The id is free again once every transaction which could take it is refused
+                if !reservation.accepted && reservation.pending == 0 {
+                    entry.remove();
+                }
+            }
+        }
+    }
+
+    fn send_batch(&mut self, shard: usize) {
+        let batch = std::mem::replace(&mut self.batches[shard], Vec::with_capacity(BATCH_SIZE));
+
+        // Workers only stop early if they panic, in which case the panic is reported by
+        // `finish`, so there's nothing useful to do with the error here.
+        let _ = self.senders[shard].send(batch);
+
+        // Outcomes are only needed when another client uses an id, but are recorded as they
+        // arrive so that they don't build up
+        while let Ok(outcomes) = self.outcomes.try_recv() {
+            self.record_outcomes(outcomes);
+        }
+    }
+
+    /// Returns errors for transactions which have been refused so far, without blocking
+    pub fn errors(&self) -> impl Iterator<Item = (T, LedgerError)> + '_ {
+        self.errors.try_iter()
+    }
+
+    /// Waits for all queued transactions to be processed, and returns the accounts from all
+    /// shards along with any errors which haven't already been returned by
+    /// [`ShardedLedger::errors`].
+    pub fn finish(mut self) -> (Vec<Account>, Vec<(T, LedgerError)>) {
+        for shard in 0..self.senders.len() {
+            self.send_batch(shard);
+        }
+
+        // Closing the queues allows the workers to finish once they are empty, and the errors
+        // can only be collected once every sender has been dropped
+        drop(self.senders);
+        drop(self.errors_sender);
+
+        let mut accounts = Vec::new();
+        for worker in self.workers {
+            match worker.join() {
+                Ok(shard_accounts) => accounts.extend(shard_accounts),
+                Err(err) => panic::resume_unwind(err),
+            }
+        }
+
+        (accounts, self.errors.into_iter().collect())
+    }
+}
+
+fn run_shard<T>(
+    batches: Receiver<Vec<Submission<T>>>,
+    errors: mpsc::Sender<(T, LedgerError)>,
+    outcomes: mpsc::Sender<Vec<(u32, bool)>>,
+) -> Vec<Account> {
+    let mut ledger = LedgerEngine::new(HashMapLedger::new());
+
+    for batch in batches {
+        let mut batch_outcomes = Vec::new();
+
+        for (transaction, tag, report_outcome) in batch {
+            let tx_id = transaction.tx_id();
+            let result = ledger.handle_transaction(transaction);
+
+            if report_outcome {
+                batch_outcomes.push((tx_id, result.is_ok()));
+            }
+            if let Err(err) = result {
+                // The receiver is only dropped once all workers have finished
+                let _ = errors.send((tag, err));
+            }
+        }
+
+        if !batch_outcomes.is_empty() {
+            // The receiver is only dropped along with the ledger
+            let _ = outcomes.send(batch_outcomes);
+        }
+    }
+
+    ledger.get_accounts().cloned().collect()
+}
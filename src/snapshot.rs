@@ -0,0 +1,120 @@
+use std::{
+    fmt,
+    fs::{self, File},
+    io::{self, BufReader, BufWriter, Write},
+    path::Path,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    hashmap_ledger::HashMapLedger,
+    ledger::{Account, AdminTransaction, LedgerStorage, StandardTransaction},
+};
+
+/// Version of the snapshot format written by [`save`]. This must be incremented whenever the
+/// format changes, so that older snapshots are refused rather than misread.
+const SNAPSHOT_VERSION: u32 = 2;
+
+/// Complete state of a ledger, written as JSON. Accounts and transactions are sorted by id so
+/// that saving the same ledger always produces the same snapshot.
+#[derive(Serialize)]
+struct Snapshot<'a> {
+    version: u32,
+    accounts: Vec<&'a Account>,
+    transactions: Vec<&'a StandardTransaction>,
+    admin_transactions: Vec<&'a AdminTransaction>,
+}
+
+/// Owned version of [`Snapshot`] for loading
+#[derive(Deserialize)]
+struct OwnedSnapshot {
+    version: u32,
+    accounts: Vec<Account>,
+    transactions: Vec<StandardTransaction>,
+    /// Missing from older snapshots, which must still be parsed to check their version
+    #[serde(default)]
+    admin_transactions: Vec<AdminTransaction>,
+}
+
+pub(crate) fn load<P: AsRef<Path>>(path: P) -> Result<HashMapLedger, Error> {
+    let file = File::open(path).map_err(Error::Io)?;
+    let snapshot: OwnedSnapshot =
+        serde_json::from_reader(BufReader::new(file)).map_err(Error::Json)?;
+
+    if snapshot.version != SNAPSHOT_VERSION {
+        return Err(Error::UnsupportedVersion(snapshot.version));
+    }
+
+    let mut ledger = HashMapLedger::new();
+    for account in snapshot.accounts {
+        ledger.put_account(account);
+    }
+    for transaction in snapshot.transactions {
+        ledger.put_transaction(transaction);
+    }
+    for transaction in snapshot.admin_transactions {
+        ledger.put_admin_transaction(transaction);
+    }
+
+    Ok(ledger)
+}
+
+/// Saves the ledger to `path`. The snapshot is written to a temporary file first, so that an
+/// existing snapshot at `path` is left intact if saving fails.
+pub(crate) fn save<P: AsRef<Path>>(path: P, ledger: &HashMapLedger) -> Result<(), Error> {
+    let mut accounts: Vec<_> = ledger.get_accounts().collect();
+    accounts.sort_by_key(|account| account.client_id);
+
+    let mut transactions: Vec<_> = ledger.get_transactions().collect();
+    transactions.sort_by_key(|transaction| transaction.tx_id);
+
+    let mut admin_transactions: Vec<_> = ledger.get_admin_transactions().collect();
+    admin_transactions.sort_by_key(|transaction| transaction.tx_id);
+
+    let snapshot = Snapshot {
+        version: SNAPSHOT_VERSION,
+        accounts,
+        transactions,
+        admin_transactions,
+    };
+
+    let path = path.as_ref();
+    let mut temp_path = path.as_os_str().to_owned();
+    temp_path.push(".tmp");
+
+    let file = File::create(&temp_path).map_err(Error::Io)?;
+    let mut writer = BufWriter::new(file);
+    serde_json::to_writer(&mut writer, &snapshot).map_err(Error::Json)?;
+    writer.write_all(b"\n").map_err(Error::Io)?;
+
+    let file = writer
+        .into_inner()
+        .map_err(|err| Error::Io(err.into_error()))?;
+    file.sync_all().map_err(Error::Io)?;
+
+    fs::rename(&temp_path, path).map_err(Error::Io)
+}
+
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    Json(serde_json::Error),
+    UnsupportedVersion(u32),
+}
+
+impl std::error::Error for Error {}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(err) => write!(f, "Failed to access snapshot: {}", err),
+            Self::Json(err) => write!(f, "Invalid snapshot: {}", err),
+            Self::UnsupportedVersion(version) => write!(
+                f,
+                "Unsupported snapshot version {}, expected version {}",
+                version, SNAPSHOT_VERSION
+            ),
+        }
+    }
+}
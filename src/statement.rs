@@ -0,0 +1,160 @@
+use std::{fmt, io};
+
+use rust_decimal::Decimal;
+use serde::Serialize;
+
+use crate::{
+    accounts_output::Format, amounts::OutputScale, history::HistoryEntry, ledger::Outcome,
+};
+
+/// One line of a client's statement: a transaction, whether it was applied, and the
+/// client's balances afterwards. Amounts are serialized as strings, as for accounts.
+#[derive(Debug, Serialize)]
+pub(crate) struct StatementRecord<'a> {
+    client: u16,
+    source: &'a str,
+    line: u64,
+    #[serde(rename = "type")]
+    tx_type: &'static str,
+    tx: u32,
+    dispute: Option<u32>,
+    amount: Option<Decimal>,
+    /// Reason code given for an administrative transaction
+    reason_code: Option<&'a str>,
+    result: StatementResult,
+    /// Machine-readable reason the transaction was rejected, matching the rejections file
+    reason: Option<&'static str>,
+    available: Decimal,
+    held: Decimal,
+    total: Decimal,
+    locked: bool,
+    closed: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum StatementResult {
+    /// The transaction was applied to the account
+    Accepted,
+    /// The transaction had already been applied, so the account wasn't changed
+    Replayed,
+    /// The transaction was refused by the ledger, so the account wasn't changed
+    Rejected,
+}
+
+impl<'a> StatementRecord<'a> {
+    pub fn new(entry: &'a HistoryEntry, scale: &OutputScale) -> Self {
+        let (result, reason) = match entry.result {
+            Ok(Outcome::Applied) => (StatementResult::Accepted, None),
+            Ok(Outcome::Replayed) => (StatementResult::Replayed, None),
+            Err(err) => (StatementResult::Rejected, Some(err.kind())),
+        };
+
+        StatementRecord {
+            client: entry.account.client_id,
+            source: &entry.source,
+            line: entry.line,
+            tx_type: entry.tx_type,
+            tx: entry.tx_id,
+            dispute: entry.dispute_id,
+            amount: entry.amount.map(|amount| scale.apply(amount)),
+            reason_code: entry.reason_code.as_deref(),
+            result,
+            reason,
+            available: scale.apply(entry.account.available),
+            held: scale.apply(entry.account.held),
+            total: scale.apply(entry.account.total()),
+            locked: entry.account.is_locked,
+            closed: entry.account.is_closed,
+        }
+    }
+}
+
+const CSV_HEADER: [&str; 15] = [
+    "client",
+    "source",
+    "line",
+    "type",
+    "tx",
+    "dispute",
+    "amount",
+    "reason_code",
+    "result",
+    "reason",
+    "available",
+    "held",
+    "total",
+    "locked",
+    "closed",
+];
+
+/// Writes statement entries in any of the account output [`Format`]s
+pub(crate) struct Writer<W: io::Write> {
+    writer: W,
+    format: Format,
+    scale: OutputScale,
+}
+
+impl<W: io::Write> Writer<W> {
+    pub fn from_writer(writer: W, format: Format, scale: OutputScale) -> Writer<W> {
+        Writer {
+            writer,
+            format,
+            scale,
+        }
+    }
+
+    pub fn write_all<'a, I: Iterator<Item = &'a HistoryEntry>>(
+        mut self,
+        entries_iterator: I,
+    ) -> Result<(), Error> {
+        let records = entries_iterator.map(|entry| StatementRecord::new(entry, &self.scale));
+
+        match self.format {
+            Format::Csv => {
+                // The header is written explicitly so that it's included even if there are no
+                // entries
+                let mut writer = csv::WriterBuilder::new()
+                    .has_headers(false)
+                    .from_writer(&mut self.writer);
+                writer.write_record(CSV_HEADER).map_err(Error::Csv)?;
+                for record in records {
+                    writer.serialize(record).map_err(Error::Csv)?;
+                }
+                writer.flush().map_err(Error::Io)?;
+            }
+            Format::Json => {
+                let records: Vec<_> = records.collect();
+                serde_json::to_writer(&mut self.writer, &records).map_err(Error::Json)?;
+                self.writer.write_all(b"\n").map_err(Error::Io)?;
+            }
+            Format::Jsonl => {
+                for record in records {
+                    serde_json::to_writer(&mut self.writer, &record).map_err(Error::Json)?;
+                    self.writer.write_all(b"\n").map_err(Error::Io)?;
+                }
+            }
+        }
+
+        self.writer.flush().map_err(Error::Io)
+    }
+}
+
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    Csv(csv::Error),
+    Json(serde_json::Error),
+}
+
+impl std::error::Error for Error {}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(err) => write!(f, "Failed to write statement: {}", err),
+            Self::Csv(err) => write!(f, "Failed to write statement CSV: {}", err),
+            Self::Json(err) => write!(f, "Failed to write statement JSON: {}", err),
+        }
+    }
+}
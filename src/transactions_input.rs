@@ -0,0 +1,55 @@
+use std::io;
+
+use clap::ValueEnum;
+
+use crate::amounts::InputPrecision;
+use crate::csv_transactions::{self, Error, TransactionRow, TypeAliases};
+use crate::jsonl_transactions;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, ValueEnum)]
+pub(crate) enum Format {
+    /// CSV with a header row
+    #[default]
+    Csv,
+    /// JSON Lines, with one transaction object per line
+    Jsonl,
+}
+
+/// Reads transactions in any of the supported input [`Format`]s. Both formats produce the
+/// same rows, so the rest of the pipeline doesn't depend on the input format.
+pub(crate) enum Reader<R> {
+    Csv(csv_transactions::Reader<R>),
+    Jsonl(jsonl_transactions::Reader<R>),
+}
+
+impl<R: io::Read> Reader<R> {
+    pub fn from_reader(reader: R, format: Format) -> Reader<R> {
+        match format {
+            Format::Csv => Reader::Csv(csv_transactions::Reader::from_reader(reader)),
+            Format::Jsonl => Reader::Jsonl(jsonl_transactions::Reader::from_reader(reader)),
+        }
+    }
+
+    /// Accept the given aliases in addition to the standard transaction type names
+    pub fn with_type_aliases(self, type_aliases: TypeAliases) -> Reader<R> {
+        match self {
+            Reader::Csv(reader) => Reader::Csv(reader.with_type_aliases(type_aliases)),
+            Reader::Jsonl(reader) => Reader::Jsonl(reader.with_type_aliases(type_aliases)),
+        }
+    }
+
+    /// Limit the number of decimal places of amounts
+    pub fn with_precision(self, precision: InputPrecision) -> Reader<R> {
+        match self {
+            Reader::Csv(reader) => Reader::Csv(reader.with_precision(precision)),
+            Reader::Jsonl(reader) => Reader::Jsonl(reader.with_precision(precision)),
+        }
+    }
+
+    pub fn iter(&mut self) -> Result<Box<dyn Iterator<Item = TransactionRow> + '_>, Error> {
+        match self {
+            Reader::Csv(reader) => Ok(Box::new(reader.iter()?)),
+            Reader::Jsonl(reader) => Ok(Box::new(reader.iter())),
+        }
+    }
+}
@@ -0,0 +1,217 @@
+use std::{
+    fmt,
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::Path,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    json_lines,
+    ledger::{Account, AdminTransaction, LedgerStorage, StandardTransaction},
+};
+
+/// Durable storage for a ledger. Every account and transaction written to the underlying
+/// storage is also appended to a write-ahead log, which is replayed into the underlying
+/// storage when the log is reopened. This allows the ledger to be built up incrementally over
+/// several runs.
+///
+/// [`LedgerStorage`] can't report errors, so the first error writing to the log is kept and
+/// returned by [`WalLedger::commit`], which should be called after each transaction.
+pub(crate) struct WalLedger<S> {
+    storage: S,
+    log: BufWriter<File>,
+    error: Option<io::Error>,
+}
+
+/// A single entry in the log, which is written as one line of JSON. Entries replace any
+/// previous account or transaction with the same id, exactly like [`LedgerStorage`].
+#[derive(Serialize)]
+#[serde(tag = "entry", rename_all = "snake_case")]
+enum LogEntry<'a> {
+    Account(&'a Account),
+    Transaction(&'a StandardTransaction),
+    AdminTransaction(&'a AdminTransaction),
+    /// All the changes made by a single transaction, which are written as one entry so that
+    /// an incomplete entry never leaves the ledger with only some of them applied
+    Commit {
+        account: &'a Account,
+        transaction: &'a StandardTransaction,
+    },
+    /// All the changes made by a single administrative transaction, as for `Commit`
+    AdminCommit {
+        account: &'a Account,
+        transaction: &'a AdminTransaction,
+    },
+}
+
+/// Owned version of [`LogEntry`] for reading the log
+#[derive(Deserialize)]
+#[serde(tag = "entry", rename_all = "snake_case")]
+enum OwnedLogEntry {
+    Account(Account),
+    Transaction(StandardTransaction),
+    AdminTransaction(AdminTransaction),
+    /// Logs written before administrative transactions were recorded have commits without
+    /// a transaction for them
+    Commit {
+        account: Account,
+        transaction: Option<StandardTransaction>,
+    },
+    AdminCommit {
+        account: Account,
+        transaction: AdminTransaction,
+    },
+}
+
+impl<'a, S: LedgerStorage<'a>> WalLedger<S> {
+    /// Opens the log at `path`, creating it if it doesn't exist, and replays it into
+    /// `storage`. An incomplete entry at the end of the log, e.g. because the program was
+    /// killed while writing it, is discarded.
+    pub fn open<P: AsRef<Path>>(path: P, mut storage: S) -> Result<WalLedger<S>, Error> {
+        let file = json_lines::open_log(path, |_, entry| -> Result<(), Error> {
+            match entry {
+                OwnedLogEntry::Account(account) => storage.put_account(account),
+                OwnedLogEntry::Transaction(transaction) => storage.put_transaction(transaction),
+                OwnedLogEntry::AdminTransaction(transaction) => {
+                    storage.put_admin_transaction(transaction)
+                }
+                OwnedLogEntry::Commit {
+                    account,
+                    transaction: Some(transaction),
+                } => storage.commit(account, transaction),
+                OwnedLogEntry::Commit {
+                    account,
+                    transaction: None,
+                } => storage.put_account(account),
+                OwnedLogEntry::AdminCommit {
+                    account,
+                    transaction,
+                } => storage.commit_admin(account, transaction),
+            }
+            Ok(())
+        })?;
+
+        Ok(WalLedger {
+            storage,
+            log: BufWriter::new(file),
+            error: None,
+        })
+    }
+}
+
+impl<S> WalLedger<S> {
+    /// Writes any buffered entries to the log, and returns the first error that occurred
+    /// while writing entries since the last commit
+    pub fn commit(&mut self) -> Result<(), Error> {
+        if let Some(err) = self.error.take() {
+            return Err(Error::Io(err));
+        }
+
+        self.log.flush().map_err(Error::Io)
+    }
+
+    /// Commits any buffered entries and waits for the log to be written to disk
+    pub fn sync(&mut self) -> Result<(), Error> {
+        self.commit()?;
+        self.log.get_ref().sync_data().map_err(Error::Io)
+    }
+
+    fn append(&mut self, entry: &LogEntry) {
+        if self.error.is_some() {
+            return;
+        }
+
+        let result = serde_json::to_writer(&mut self.log, entry)
+            .map_err(io::Error::from)
+            .and_then(|_| self.log.write_all(b"\n"));
+
+        if let Err(err) = result {
+            self.error = Some(err);
+        }
+    }
+}
+
+impl<'a, S: LedgerStorage<'a>> LedgerStorage<'a> for WalLedger<S> {
+    type AccountsIterator = S::AccountsIterator;
+
+    fn get_accounts(&'a self) -> Self::AccountsIterator {
+        self.storage.get_accounts()
+    }
+
+    fn get_account(&self, client_id: u16) -> Option<Account> {
+        self.storage.get_account(client_id)
+    }
+
+    fn put_account(&mut self, account: Account) {
+        self.append(&LogEntry::Account(&account));
+        self.storage.put_account(account);
+    }
+
+    fn get_transaction(&self, tx_id: u32) -> Option<StandardTransaction> {
+        self.storage.get_transaction(tx_id)
+    }
+
+    fn contains_transaction(&self, tx_id: u32) -> bool {
+        self.storage.contains_transaction(tx_id)
+    }
+
+    fn put_transaction(&mut self, transaction: StandardTransaction) {
+        self.append(&LogEntry::Transaction(&transaction));
+        self.storage.put_transaction(transaction);
+    }
+
+    fn get_admin_transaction(&self, tx_id: u32) -> Option<AdminTransaction> {
+        self.storage.get_admin_transaction(tx_id)
+    }
+
+    fn put_admin_transaction(&mut self, transaction: AdminTransaction) {
+        self.append(&LogEntry::AdminTransaction(&transaction));
+        self.storage.put_admin_transaction(transaction);
+    }
+
+    fn commit(&mut self, account: Account, transaction: StandardTransaction) {
+        self.append(&LogEntry::Commit {
+            account: &account,
+            transaction: &transaction,
+        });
+        self.storage.commit(account, transaction);
+    }
+
+    fn commit_admin(&mut self, account: Account, transaction: AdminTransaction) {
+        self.append(&LogEntry::AdminCommit {
+            account: &account,
+            transaction: &transaction,
+        });
+        self.storage.commit_admin(account, transaction);
+    }
+}
+
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    Corrupt { line: u64, err: serde_json::Error },
+}
+
+impl From<json_lines::Error> for Error {
+    fn from(err: json_lines::Error) -> Error {
+        match err {
+            json_lines::Error::Io(err) => Error::Io(err),
+            json_lines::Error::Corrupt { line, err } => Error::Corrupt { line, err },
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(err) => write!(f, "Failed to access write-ahead log: {}", err),
+            Self::Corrupt { line, err } => {
+                write!(f, "Write-ahead log is corrupt at line {}: {}", line, err)
+            }
+        }
+    }
+}
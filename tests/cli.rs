@@ -71,14 +71,15 @@ deposit, 1, 1,",
 }
 
 #[test]
-fn command_fails_when_amount_present_for_dispute() -> Result<(), Box<dyn std::error::Error>> {
+fn command_fails_when_amount_present_for_resolve() -> Result<(), Box<dyn std::error::Error>> {
     let csv_file = assert_fs::NamedTempFile::new("transactions.csv")?;
 
-    // Note that the deposit is missing a column for amount.
+    // Note that only disputes may specify an amount.
     csv_file.write_str(
         "type, client, tx, amount
 deposit, 1, 1, 5.0
-dispute, 1, 1, 3.0",
+dispute, 1, 1,
+resolve, 1, 1, 3.0",
     )?;
 
     let mut cmd = Command::cargo_bin(BIN_NAME)?;
@@ -205,10 +206,2398 @@ chargeback, 1, 1,";
     assert_cmd_succeeds_with_result(csv_content, expected_rows)
 }
 
+/// Disputing a withdrawal gives the client a provisional credit, which is held
+/// until the dispute is resolved or charged back.
+#[test]
+fn dispute_withdrawal() -> Result<(), Box<dyn std::error::Error>> {
+    let csv_content = "type, client, tx, amount
+deposit, 1, 1, 2.5
+withdrawal, 1, 2, 1.0
+dispute, 1, 2,";
+
+    let expected_rows = &mut ["1,1.5,1,2.5,false"];
+
+    assert_cmd_succeeds_with_result(csv_content, expected_rows)
+}
+
+/// Resolving a withdrawal dispute means the withdrawal stands, so the provisional
+/// credit is removed.
+#[test]
+fn dispute_withdrawal_then_resolve() -> Result<(), Box<dyn std::error::Error>> {
+    let csv_content = "type, client, tx, amount
+deposit, 1, 1, 2.5
+withdrawal, 1, 2, 1.0
+dispute, 1, 2,
+withdrawal, 1, 3, 1.0
+resolve, 1, 2,";
+
+    let expected_rows = &mut ["1,0.5,0,0.5,false"];
+
+    assert_cmd_succeeds_with_result(csv_content, expected_rows)
+}
+
+/// Charging back a withdrawal returns the withdrawn funds to the client and locks
+/// the account.
+#[test]
+fn dispute_withdrawal_then_chargeback() -> Result<(), Box<dyn std::error::Error>> {
+    let csv_content = "type, client, tx, amount
+deposit, 1, 1, 2.5
+withdrawal, 1, 2, 1.0
+dispute, 1, 2,
+chargeback, 1, 2,
+deposit, 1, 3, 1.0";
+
+    let expected_rows = &mut ["1,2.5,0,2.5,true"];
+
+    assert_cmd_succeeds_with_result(csv_content, expected_rows)
+}
+
+/// Several disputes can be open against a transaction when they have distinct dispute
+/// ids. Each holds its own amount until it is resolved.
+#[test]
+fn concurrent_disputes_with_dispute_ids() -> Result<(), Box<dyn std::error::Error>> {
+    let csv_content = "type, client, tx, amount, dispute
+deposit, 1, 1, 1.5,
+deposit, 1, 2, 1.21,
+dispute, 1, 1, 0.5, 1
+dispute, 1, 1, 0.26, 2
+resolve, 1, 1, , 1
+withdrawal, 1, 3, 2.5,";
+
+    let expected_rows = &mut ["1,2.45,0.26,2.71,false"];
+
+    assert_cmd_succeeds_with_result(csv_content, expected_rows)
+}
+
+#[test]
+fn concurrent_disputes_all_resolved() -> Result<(), Box<dyn std::error::Error>> {
+    let csv_content = "type, client, tx, amount, dispute
+deposit, 1, 1, 3.0,
+dispute, 1, 1, 1.0, 1
+dispute, 1, 1, 2.0, 2
+resolve, 1, 1, , 2
+resolve, 1, 1, , 1";
+
+    let expected_rows = &mut ["1,3,0,3,false"];
+
+    assert_cmd_succeeds_with_result(csv_content, expected_rows)
+}
+
+/// The same dispute id can't be opened twice, and resolving it once releases the funds.
+#[test]
+fn duplicate_dispute_id_ignored() -> Result<(), Box<dyn std::error::Error>> {
+    let csv_content = "type, client, tx, amount, dispute
+deposit, 1, 1, 1.0,
+dispute, 1, 1, , 7
+dispute, 1, 1, , 7
+resolve, 1, 1, , 7";
+
+    let expected_rows = &mut ["1,1,0,1,false"];
+
+    assert_cmd_succeeds_with_result(csv_content, expected_rows)
+}
+
+/// Charging back one dispute only reverses the amount of that dispute. Other disputes
+/// against the transaction remain open, and the charged back amount can't be disputed again.
+#[test]
+fn chargeback_leaves_other_disputes_open() -> Result<(), Box<dyn std::error::Error>> {
+    let csv_content = "type, client, tx, amount, dispute
+deposit, 1, 1, 1.5,
+dispute, 1, 1, 0.51, 1
+dispute, 1, 1, 0.25, 2
+chargeback, 1, 1, , 2
+dispute, 1, 1, 1.0, 3";
+
+    let expected_rows = &mut ["1,0.74,0.51,1.25,true"];
+
+    assert_cmd_succeeds_with_result(csv_content, expected_rows)
+}
+
+/// A dispute without an amount is for whatever part of the transaction hasn't already
+/// been disputed. Once the full amount is disputed, further disputes are rejected.
+#[test]
+fn partial_dispute_then_dispute_remainder() -> Result<(), Box<dyn std::error::Error>> {
+    let csv_content = "type, client, tx, amount, dispute
+deposit, 1, 1, 1.55,
+deposit, 1, 2, 1.21,
+dispute, 1, 1, 0.24, 1
+dispute, 1, 1, , 2
+dispute, 1, 1, , 3";
+
+    let expected_rows = &mut ["1,1.21,1.55,2.76,false"];
+
+    assert_cmd_succeeds_with_result(csv_content, expected_rows)
+}
+
+#[test]
+fn partial_dispute_then_chargeback() -> Result<(), Box<dyn std::error::Error>> {
+    let csv_content = "type, client, tx, amount
+deposit, 1, 1, 2.5
+dispute, 1, 1, 1.0
+chargeback, 1, 1,";
+
+    let expected_rows = &mut ["1,1.5,0,1.5,true"];
+
+    assert_cmd_succeeds_with_result(csv_content, expected_rows)
+}
+
+#[test]
+fn dispute_exceeding_transaction_amount_ignored() -> Result<(), Box<dyn std::error::Error>> {
+    let csv_content = "type, client, tx, amount, dispute
+deposit, 1, 1, 1.5,
+dispute, 1, 1, 1.0, 1
+dispute, 1, 1, 0.75, 2
+dispute, 1, 1, -0.25, 3";
+
+    let expected_rows = &mut ["1,0.5,1,1.5,false"];
+
+    assert_cmd_succeeds_with_result(csv_content, expected_rows)
+}
+
+#[test]
+fn command_fails_when_dispute_id_present_for_deposit() -> Result<(), Box<dyn std::error::Error>> {
+    let csv_file = assert_fs::NamedTempFile::new("transactions.csv")?;
+    csv_file.write_str(
+        "type, client, tx, amount, dispute
+deposit, 1, 1, 1.0, 1",
+    )?;
+
+    let mut cmd = Command::cargo_bin(BIN_NAME)?;
+    cmd.arg(csv_file.path());
+
+    cmd.assert()
+        .failure()
+        .stderr(predicate::str::contains("DisputeIdUnexpected"));
+
+    Ok(())
+}
+
+#[test]
+fn rejections_written_as_csv() -> Result<(), Box<dyn std::error::Error>> {
+    let csv_file = assert_fs::NamedTempFile::new("transactions.csv")?;
+    csv_file.write_str(
+        "type, client, tx, amount
+deposit, 1, 1, 1.0
+withdrawal, 1, 2, 1.50
+dispute, 2, 1,
+resolve, 1, 1,",
+    )?;
+
+    let rejections_file = assert_fs::NamedTempFile::new("rejections.csv")?;
+
+    let mut cmd = Command::cargo_bin(BIN_NAME)?;
+    cmd.arg(csv_file.path())
+        .arg("--rejections")
+        .arg(rejections_file.path());
+    cmd.assert().success();
+
+    let source = csv_file.path().display();
+    rejections_file.assert(format!(
+        "source,type,client,tx,amount,line,reason,message
+{source},withdrawal,1,2,1.50,3,insufficient_funds,Insufficient funds available for client id 1 to process withdrawal 2
+{source},dispute,2,1,,4,client_mismatch,Transaction with id 1 does not belong to client 2
+{source},resolve,1,1,,5,not_disputed,Transaction 1 not disputed
+"
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn rejections_written_as_jsonl() -> Result<(), Box<dyn std::error::Error>> {
+    let csv_file = assert_fs::NamedTempFile::new("transactions.csv")?;
+    csv_file.write_str(
+        "type, client, tx, amount
+deposit, 1, 1, 1.0
+deposit, 1, 2, -1.0",
+    )?;
+
+    let rejections_file = assert_fs::NamedTempFile::new("rejections.jsonl")?;
+
+    let mut cmd = Command::cargo_bin(BIN_NAME)?;
+    cmd.arg(csv_file.path())
+        .arg("--rejections")
+        .arg(rejections_file.path())
+        .arg("--rejections-format")
+        .arg("jsonl");
+    cmd.assert().success();
+
+    rejections_file.assert(format!(
+        concat!(
+            r#"{{"source":"{}","type":"deposit","client":"1","tx":"2","amount":"-1.0","line":3,"#,
+            r#""reason":"non_positive_amount","message":"Amount must be greater than zero for transaction 2"}}"#,
+            "\n"
+        ),
+        csv_file.path().display()
+    ));
+
+    Ok(())
+}
+
+const INVALID_ROWS_CSV: &str = "type, client, tx, amount
+deposit, 1, 1, 1.0
+deposit, 1, 2
+deposit, 1, 3,
+resolve, 1, 1, 2.0
+deposit, 1, 4, 2.0";
+
+#[test]
+fn skip_invalid_policy_skips_and_reports_invalid_rows() -> Result<(), Box<dyn std::error::Error>> {
+    let csv_file = assert_fs::NamedTempFile::new("transactions.csv")?;
+    csv_file.write_str(INVALID_ROWS_CSV)?;
+
+    let rejections_file = assert_fs::NamedTempFile::new("rejections.csv")?;
+
+    let mut cmd = Command::cargo_bin(BIN_NAME)?;
+    cmd.arg(csv_file.path())
+        .arg("--error-policy")
+        .arg("skip-invalid")
+        .arg("--rejections")
+        .arg(rejections_file.path());
+    cmd.assert()
+        .success()
+        .stdout("client,available,held,total,locked\n1,3,0,3,false\n");
+
+    rejections_file.assert(
+        predicate::str::contains("deposit,1,2,,3,invalid_csv,")
+            .and(predicate::str::contains(
+                "deposit,1,3,,4,amount_not_specified,",
+            ))
+            .and(predicate::str::contains(
+                "resolve,1,1,2.0,5,amount_unexpected_for_dispute,",
+            )),
+    );
+
+    Ok(())
+}
+
+#[test]
+fn lenient_policy_succeeds_within_max_errors() -> Result<(), Box<dyn std::error::Error>> {
+    let csv_file = assert_fs::NamedTempFile::new("transactions.csv")?;
+    csv_file.write_str(INVALID_ROWS_CSV)?;
+
+    let mut cmd = Command::cargo_bin(BIN_NAME)?;
+    cmd.arg(csv_file.path())
+        .arg("--error-policy")
+        .arg("lenient")
+        .arg("--max-errors")
+        .arg("3");
+    cmd.assert()
+        .success()
+        .stdout("client,available,held,total,locked\n1,3,0,3,false\n");
+
+    Ok(())
+}
+
+#[test]
+fn lenient_policy_fails_after_max_errors() -> Result<(), Box<dyn std::error::Error>> {
+    let csv_file = assert_fs::NamedTempFile::new("transactions.csv")?;
+    csv_file.write_str(INVALID_ROWS_CSV)?;
+
+    let mut cmd = Command::cargo_bin(BIN_NAME)?;
+    cmd.arg(csv_file.path())
+        .arg("--error-policy")
+        .arg("lenient")
+        .arg("--max-errors")
+        .arg("2");
+    cmd.assert()
+        .failure()
+        .stderr(predicate::str::contains("AmountUnexpectedForDispute"));
+
+    Ok(())
+}
+
+#[test]
+fn multiple_input_files_processed_in_order() -> Result<(), Box<dyn std::error::Error>> {
+    let first_csv_file = assert_fs::NamedTempFile::new("first.csv")?;
+    first_csv_file.write_str(
+        "type, client, tx, amount
+deposit, 1, 1, 1.0
+deposit, 2, 2, 2.0",
+    )?;
+
+    let second_csv_file = assert_fs::NamedTempFile::new("second.csv")?;
+    second_csv_file.write_str(
+        "type, client, tx, amount
+withdrawal, 1, 3, 0.5
+dispute, 2, 2,",
+    )?;
+
+    let mut cmd = Command::cargo_bin(BIN_NAME)?;
+    cmd.arg(first_csv_file.path()).arg(second_csv_file.path());
+
+    let assertion = cmd.assert().success();
+    let output = std::str::from_utf8(&assertion.get_output().stdout)?;
+    let mut rows: Vec<&str> = output.trim().split('\n').skip(1).collect();
+    rows.sort();
+
+    assert_eq!(["1,0.5,0,0.5,false", "2,0,2,2,false"], *rows);
+
+    Ok(())
+}
+
+#[test]
+fn transactions_read_from_stdin() -> Result<(), Box<dyn std::error::Error>> {
+    let csv_file = assert_fs::NamedTempFile::new("transactions.csv")?;
+    csv_file.write_str(
+        "type, client, tx, amount
+deposit, 1, 1, 1.0",
+    )?;
+
+    let mut cmd = assert_cmd::Command::cargo_bin(BIN_NAME)?;
+    cmd.arg(csv_file.path()).arg("-").write_stdin(
+        "type, client, tx, amount
+withdrawal, 1, 2, 0.25",
+    );
+
+    cmd.assert()
+        .success()
+        .stdout("client,available,held,total,locked\n1,0.75,0,0.75,false\n");
+
+    Ok(())
+}
+
+#[test]
+fn transaction_types_are_case_insensitive() -> Result<(), Box<dyn std::error::Error>> {
+    let csv_content = "type, client, tx, amount
+Deposit, 1, 1, 2.0
+WITHDRAWAL, 1, 2, 0.5
+DisPute, 1, 1,";
+
+    let expected_rows = &mut ["1,-0.5,2,1.5,false"];
+
+    assert_cmd_succeeds_with_result(csv_content, expected_rows)
+}
+
+#[test]
+fn transaction_type_aliases() -> Result<(), Box<dyn std::error::Error>> {
+    let csv_file = assert_fs::NamedTempFile::new("transactions.csv")?;
+    csv_file.write_str(
+        "type, client, tx, amount
+deposit, 1, 1, 2.0
+withdraw, 1, 2, 0.5
+WITHDRAW, 1, 3, 0.25",
+    )?;
+
+    let mut cmd = Command::cargo_bin(BIN_NAME)?;
+    cmd.arg(csv_file.path())
+        .arg("--type-alias")
+        .arg("withdraw=withdrawal");
+
+    cmd.assert()
+        .success()
+        .stdout("client,available,held,total,locked\n1,1.25,0,1.25,false\n");
+
+    Ok(())
+}
+
+#[test]
+fn command_fails_when_transaction_type_unknown() -> Result<(), Box<dyn std::error::Error>> {
+    let csv_file = assert_fs::NamedTempFile::new("transactions.csv")?;
+    csv_file.write_str(
+        "type, client, tx, amount
+withdraw, 1, 1, 2.0",
+    )?;
+
+    let mut cmd = Command::cargo_bin(BIN_NAME)?;
+    cmd.arg(csv_file.path());
+
+    cmd.assert()
+        .failure()
+        .stderr(predicate::str::contains("UnknownType(\"withdraw\")"));
+
+    Ok(())
+}
+
+#[test]
+fn command_fails_when_type_alias_invalid() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = Command::cargo_bin(BIN_NAME)?;
+    cmd.arg("transactions.csv")
+        .arg("--type-alias")
+        .arg("withdraw=withdrawl");
+
+    cmd.assert().failure().stderr(predicate::str::contains(
+        "Unknown transaction type 'withdrawl'",
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn unlock_after_chargeback() -> Result<(), Box<dyn std::error::Error>> {
+    let csv_content = "type, client, tx, amount
+deposit, 1, 1, 2.5
+deposit, 1, 2, 1.5
+dispute, 1, 2,
+chargeback, 1, 2,
+deposit, 1, 3, 1.25
+unlock, 1, 4,
+deposit, 1, 5, 1.5";
+
+    assert_cmd_succeeds_with_result(csv_content, &mut ["1,4,0,4,false"])
+}
+
+#[test]
+fn freeze_prevents_deposits_and_withdrawals() -> Result<(), Box<dyn std::error::Error>> {
+    let csv_content = "type, client, tx, amount
+deposit, 1, 1, 2.5
+freeze, 1, 2,
+deposit, 1, 3, 1.5
+withdrawal, 1, 4, 1.5";
+
+    assert_cmd_succeeds_with_result(csv_content, &mut ["1,2.5,0,2.5,true"])
+}
+
+#[test]
+fn admin_transaction_for_unknown_account_ignored() -> Result<(), Box<dyn std::error::Error>> {
+    let csv_content = "type, client, tx, amount
+deposit, 1, 1, 2.5
+freeze, 2, 2,";
+
+    assert_cmd_succeeds_with_result(csv_content, &mut ["1,2.5,0,2.5,false"])
+}
+
+#[test]
+fn close_requires_empty_account() -> Result<(), Box<dyn std::error::Error>> {
+    let csv_content = "type, client, tx, amount
+deposit, 1, 1, 2.5
+deposit, 2, 2, 1.5
+close, 1, 3,
+withdrawal, 2, 4, 1.5
+close, 2, 5,
+deposit, 2, 6, 1.5";
+
+    assert_cmd_succeeds_with_result(csv_content, &mut ["1,2.5,0,2.5,false", "2,0,0,0,true"])
+}
+
+#[test]
+fn closed_account_cannot_be_unlocked() -> Result<(), Box<dyn std::error::Error>> {
+    let csv_content = "type, client, tx, amount
+deposit, 1, 1, 1.5
+withdrawal, 1, 2, 1.5
+close, 1, 3,
+unlock, 1, 4,
+deposit, 1, 5, 2.5";
+
+    assert_cmd_succeeds_with_result(csv_content, &mut ["1,0,0,0,true"])
+}
+
+#[test]
+fn adjustments_with_reason() -> Result<(), Box<dyn std::error::Error>> {
+    let csv_content = "type, client, tx, amount, reason
+deposit, 1, 1, 2.5,
+deposit, 1, 2, 1.5,
+dispute, 1, 2, ,
+chargeback, 1, 2, ,
+adjustment, 1, 3, 0.25, goodwill
+adjustment, 1, 4, -1.5, duplicate_payout
+adjustment, 1, 5, 0, noop";
+
+    assert_cmd_succeeds_with_result(csv_content, &mut ["1,1.25,0,1.25,true"])
+}
+
+#[test]
+fn command_fails_when_reason_missing_for_adjustment() -> Result<(), Box<dyn std::error::Error>> {
+    let csv_file = assert_fs::NamedTempFile::new("transactions.csv")?;
+    csv_file.write_str(
+        "type, client, tx, amount, reason
+deposit, 1, 1, 2.5,
+adjustment, 1, 2, 1.5,",
+    )?;
+
+    let mut cmd = Command::cargo_bin(BIN_NAME)?;
+    cmd.arg(csv_file.path());
+
+    cmd.assert()
+        .failure()
+        .stderr(predicate::str::contains("ReasonNotSpecified"));
+
+    Ok(())
+}
+
+#[test]
+fn command_fails_when_reason_present_for_deposit() -> Result<(), Box<dyn std::error::Error>> {
+    let csv_file = assert_fs::NamedTempFile::new("transactions.csv")?;
+    csv_file.write_str(
+        "type, client, tx, amount, reason
+deposit, 1, 1, 2.5, goodwill",
+    )?;
+
+    let mut cmd = Command::cargo_bin(BIN_NAME)?;
+    cmd.arg(csv_file.path());
+
+    cmd.assert()
+        .failure()
+        .stderr(predicate::str::contains("ReasonUnexpected"));
+
+    Ok(())
+}
+
+#[test]
+fn accounts_written_as_json() -> Result<(), Box<dyn std::error::Error>> {
+    let csv_file = assert_fs::NamedTempFile::new("transactions.csv")?;
+    csv_file.write_str(
+        "type, client, tx, amount
+deposit, 1, 1, 2.5
+deposit, 1, 2, 1.5
+dispute, 1, 2,",
+    )?;
+
+    let mut cmd = Command::cargo_bin(BIN_NAME)?;
+    cmd.arg(csv_file.path()).arg("--output-format").arg("json");
+
+    cmd.assert().success().stdout(concat!(
+        r#"[{"client":1,"available":"2.5","held":"1.5","total":"4","locked":false}]"#,
+        "\n"
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn accounts_written_as_jsonl() -> Result<(), Box<dyn std::error::Error>> {
+    let csv_file = assert_fs::NamedTempFile::new("transactions.csv")?;
+    csv_file.write_str(
+        "type, client, tx, amount
+deposit, 1, 1, 2.5
+deposit, 2, 2, 1.5
+freeze, 2, 3,",
+    )?;
+
+    let mut cmd = Command::cargo_bin(BIN_NAME)?;
+    cmd.arg(csv_file.path()).arg("--output-format").arg("jsonl");
+
+    let assertion = cmd.assert().success();
+
+    let output = std::str::from_utf8(&assertion.get_output().stdout)?;
+    let mut rows: Vec<&str> = output.lines().collect();
+    rows.sort(); // Sort them because order is not important in the results
+
+    assert_eq!(
+        vec![
+            r#"{"client":1,"available":"2.5","held":"0","total":"2.5","locked":false}"#,
+            r#"{"client":2,"available":"1.5","held":"0","total":"1.5","locked":true}"#,
+        ],
+        rows
+    );
+
+    Ok(())
+}
+
+#[test]
+fn transactions_read_from_jsonl() -> Result<(), Box<dyn std::error::Error>> {
+    let jsonl_file = assert_fs::NamedTempFile::new("transactions.jsonl")?;
+    jsonl_file.write_str(
+        r#"{"type": "deposit", "client": 1, "tx": 1, "amount": "2.5"}
+{"type": "deposit", "client": 1, "tx": 2, "amount": 1.5}
+
+{"type": "dispute", "client": 1, "tx": 2, "amount": null}
+{"type": "withdrawal", "client": 2, "tx": 3, "amount": "1.0"}
+"#,
+    )?;
+
+    let mut cmd = Command::cargo_bin(BIN_NAME)?;
+    cmd.arg(jsonl_file.path())
+        .arg("--input-format")
+        .arg("jsonl")
+        .arg("--output-format")
+        .arg("jsonl");
+
+    let assertion = cmd.assert().success();
+
+    let output = std::str::from_utf8(&assertion.get_output().stdout)?;
+    let mut rows: Vec<&str> = output.lines().collect();
+    rows.sort(); // Sort them because order is not important in the results
+
+    assert_eq!(
+        vec![r#"{"client":1,"available":"2.5","held":"1.5","total":"4","locked":false}"#,],
+        rows
+    );
+
+    Ok(())
+}
+
+#[test]
+fn invalid_jsonl_rows_skipped_and_reported() -> Result<(), Box<dyn std::error::Error>> {
+    let jsonl_file = assert_fs::NamedTempFile::new("transactions.jsonl")?;
+    jsonl_file.write_str(
+        r#"{"type": "deposit", "client": 1, "tx": 1, "amount": "2.5"}
+{"type": "deposit", "client": 1
+{"type": "withdrawal", "client": 1, "tx": 3}
+{"type": "resolve", "client": 1, "tx": 1, "amount": "1.5"}"#,
+    )?;
+
+    let rejections_file = assert_fs::NamedTempFile::new("rejections.csv")?;
+
+    let mut cmd = Command::cargo_bin(BIN_NAME)?;
+    cmd.arg(jsonl_file.path())
+        .arg("--input-format")
+        .arg("jsonl")
+        .arg("--error-policy")
+        .arg("skip-invalid")
+        .arg("--rejections")
+        .arg(rejections_file.path());
+
+    cmd.assert().success().stdout(
+        "client,available,held,total,locked
+1,2.5,0,2.5,false
+",
+    );
+
+    let source = jsonl_file.path().display();
+    rejections_file.assert(format!(
+        "source,type,client,tx,amount,line,reason,message
+{source},,,,,2,invalid_json,JSON error: EOF while parsing an object at line 1 column 31
+{source},withdrawal,1,3,,3,amount_not_specified,Invalid transaction: Amount not specified
+{source},resolve,1,1,1.5,4,amount_unexpected_for_dispute,Invalid transaction: Amount should not be specified for resolve/chargeback transactions
+"
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn command_fails_when_jsonl_invalid() -> Result<(), Box<dyn std::error::Error>> {
+    let jsonl_file = assert_fs::NamedTempFile::new("transactions.jsonl")?;
+    jsonl_file.write_str(r#"{"type": "deposit", "client": 1, "tx": 1}"#)?;
+
+    let mut cmd = Command::cargo_bin(BIN_NAME)?;
+    cmd.arg(jsonl_file.path())
+        .arg("--input-format")
+        .arg("jsonl");
+
+    cmd.assert()
+        .failure()
+        .stderr(predicate::str::contains("AmountNotSpecified"));
+
+    Ok(())
+}
+
+const SORTING_CSV: &str = "type, client, tx, amount
+deposit, 3, 1, 1.5
+deposit, 1, 2, 2.5
+deposit, 4, 3, 1.5
+deposit, 2, 4, 3.5
+dispute, 2, 4,";
+
+#[test]
+fn accounts_sorted_by_client_by_default() -> Result<(), Box<dyn std::error::Error>> {
+    let csv_file = assert_fs::NamedTempFile::new("transactions.csv")?;
+    csv_file.write_str(SORTING_CSV)?;
+
+    let mut cmd = Command::cargo_bin(BIN_NAME)?;
+    cmd.arg(csv_file.path());
+
+    cmd.assert().success().stdout(
+        "client,available,held,total,locked
+1,2.5,0,2.5,false
+2,0,3.5,3.5,false
+3,1.5,0,1.5,false
+4,1.5,0,1.5,false
+",
+    );
+
+    Ok(())
+}
+
+#[test]
+fn accounts_sorted_by_total() -> Result<(), Box<dyn std::error::Error>> {
+    let csv_file = assert_fs::NamedTempFile::new("transactions.csv")?;
+    csv_file.write_str(SORTING_CSV)?;
+
+    let mut cmd = Command::cargo_bin(BIN_NAME)?;
+    cmd.arg(csv_file.path()).arg("--sort-by").arg("total");
+
+    cmd.assert().success().stdout(
+        "client,available,held,total,locked
+3,1.5,0,1.5,false
+4,1.5,0,1.5,false
+1,2.5,0,2.5,false
+2,0,3.5,3.5,false
+",
+    );
+
+    Ok(())
+}
+
+#[test]
+fn accounts_sorted_by_available() -> Result<(), Box<dyn std::error::Error>> {
+    let csv_file = assert_fs::NamedTempFile::new("transactions.csv")?;
+    csv_file.write_str(SORTING_CSV)?;
+
+    let mut cmd = Command::cargo_bin(BIN_NAME)?;
+    cmd.arg(csv_file.path()).arg("--sort-by").arg("available");
+
+    cmd.assert().success().stdout(
+        "client,available,held,total,locked
+2,0,3.5,3.5,false
+3,1.5,0,1.5,false
+4,1.5,0,1.5,false
+1,2.5,0,2.5,false
+",
+    );
+
+    Ok(())
+}
+
+#[test]
+fn trailing_zeros_omitted_from_output() -> Result<(), Box<dyn std::error::Error>> {
+    let csv_content = "type, client, tx, amount
+deposit, 1, 1, 1.50000
+deposit, 1, 2, 2.5
+deposit, 2, 3, 2.0";
+
+    assert_cmd_succeeds_with_result(csv_content, &mut ["1,4,0,4,false", "2,2,0,2,false"])
+}
+
+#[test]
+fn command_fails_when_amount_too_precise() -> Result<(), Box<dyn std::error::Error>> {
+    let csv_file = assert_fs::NamedTempFile::new("transactions.csv")?;
+    csv_file.write_str(
+        "type, client, tx, amount
+deposit, 1, 1, 1.00005",
+    )?;
+
+    let mut cmd = Command::cargo_bin(BIN_NAME)?;
+    cmd.arg(csv_file.path());
+
+    cmd.assert()
+        .failure()
+        .stderr(predicate::str::contains("AmountTooPrecise(4)"));
+
+    Ok(())
+}
+
+#[test]
+fn command_fails_when_amount_invalid() -> Result<(), Box<dyn std::error::Error>> {
+    let csv_file = assert_fs::NamedTempFile::new("transactions.csv")?;
+    csv_file.write_str(
+        "type, client, tx, amount
+deposit, 1, 1, 1.5.0",
+    )?;
+
+    let mut cmd = Command::cargo_bin(BIN_NAME)?;
+    cmd.arg(csv_file.path());
+
+    cmd.assert()
+        .failure()
+        .stderr(predicate::str::contains("AmountInvalid(\"1.5.0\")"));
+
+    Ok(())
+}
+
+#[test]
+fn excess_precision_rounded() -> Result<(), Box<dyn std::error::Error>> {
+    let csv_file = assert_fs::NamedTempFile::new("transactions.csv")?;
+    csv_file.write_str(
+        "type, client, tx, amount
+deposit, 1, 1, 1.125
+deposit, 2, 2, 1.135
+deposit, 3, 3, 1.1",
+    )?;
+
+    let mut cmd = Command::cargo_bin(BIN_NAME)?;
+    cmd.arg(csv_file.path())
+        .arg("--precision")
+        .arg("2")
+        .arg("--excess-precision")
+        .arg("round");
+
+    // Midpoints are rounded to the nearest even digit by default
+    cmd.assert().success().stdout(
+        "client,available,held,total,locked
+1,1.12,0,1.12,false
+2,1.14,0,1.14,false
+3,1.1,0,1.1,false
+",
+    );
+
+    Ok(())
+}
+
+#[test]
+fn excess_precision_rounded_with_rounding_mode() -> Result<(), Box<dyn std::error::Error>> {
+    let csv_file = assert_fs::NamedTempFile::new("transactions.csv")?;
+    csv_file.write_str(
+        "type, client, tx, amount
+deposit, 1, 1, 1.125
+deposit, 2, 2, 1.131",
+    )?;
+
+    let mut cmd = Command::cargo_bin(BIN_NAME)?;
+    cmd.arg(csv_file.path())
+        .arg("--precision")
+        .arg("2")
+        .arg("--excess-precision")
+        .arg("round")
+        .arg("--rounding")
+        .arg("half-up");
+
+    cmd.assert().success().stdout(
+        "client,available,held,total,locked
+1,1.13,0,1.13,false
+2,1.13,0,1.13,false
+",
+    );
+
+    Ok(())
+}
+
+#[test]
+fn output_written_with_fixed_scale() -> Result<(), Box<dyn std::error::Error>> {
+    let csv_file = assert_fs::NamedTempFile::new("transactions.csv")?;
+    csv_file.write_str(
+        "type, client, tx, amount
+deposit, 1, 1, 1.5
+deposit, 1, 2, 2.25
+dispute, 1, 2,
+deposit, 2, 3, 0.0005",
+    )?;
+
+    let mut cmd = Command::cargo_bin(BIN_NAME)?;
+    cmd.arg(csv_file.path()).arg("--output-scale").arg("3");
+
+    cmd.assert().success().stdout(
+        "client,available,held,total,locked
+1,1.500,2.250,3.750,false
+2,0.000,0.000,0.000,false
+",
+    );
+
+    Ok(())
+}
+
+#[test]
+fn sharded_processing_matches_single_threaded() -> Result<(), Box<dyn std::error::Error>> {
+    let csv_file = assert_fs::NamedTempFile::new("transactions.csv")?;
+    csv_file.write_str(
+        "type, client, tx, amount
+deposit, 1, 1, 2.5
+deposit, 2, 2, 1.5
+deposit, 3, 3, 1.25
+withdrawal, 1, 4, 1.5
+dispute, 2, 2,
+deposit, 4, 5, 3.5
+withdrawal, 3, 6, 2.0
+chargeback, 2, 2,
+deposit, 2, 7, 1.0
+dispute, 4, 5,
+resolve, 4, 5,",
+    )?;
+
+    let mut cmd = Command::cargo_bin(BIN_NAME)?;
+    cmd.arg(csv_file.path()).arg("--shards").arg("3");
+
+    cmd.assert().success().stdout(
+        "client,available,held,total,locked
+1,1,0,1,false
+2,0,0,0,true
+3,1.25,0,1.25,false
+4,3.5,0,3.5,false
+",
+    );
+
+    Ok(())
+}
+
+#[test]
+fn sharded_processing_reports_rejections() -> Result<(), Box<dyn std::error::Error>> {
+    let csv_file = assert_fs::NamedTempFile::new("transactions.csv")?;
+    csv_file.write_str(
+        "type, client, tx, amount
+deposit, 1, 1, 1.5
+deposit, 2, 2, 2.5
+withdrawal, 1, 3, 2.5
+resolve, 2, 2,",
+    )?;
+
+    let rejections_file = assert_fs::NamedTempFile::new("rejections.csv")?;
+
+    let mut cmd = Command::cargo_bin(BIN_NAME)?;
+    cmd.arg(csv_file.path())
+        .arg("--shards")
+        .arg("2")
+        .arg("--rejections")
+        .arg(rejections_file.path());
+    cmd.assert().success();
+
+    // Rejections from different shards may be written in any order
+    let rejections = std::fs::read_to_string(rejections_file.path())?;
+    let mut rows: Vec<&str> = rejections.lines().skip(1).collect();
+    rows.sort();
+
+    let source = csv_file.path().display();
+    assert_eq!(
+        vec![
+            format!("{source},resolve,2,2,,5,not_disputed,Transaction 2 not disputed"),
+            format!("{source},withdrawal,1,3,2.5,4,insufficient_funds,Insufficient funds available for client id 1 to process withdrawal 3"),
+        ],
+        rows
+    );
+
+    Ok(())
+}
+
+#[test]
+fn sharded_processing_checks_transaction_ids_across_shards(
+) -> Result<(), Box<dyn std::error::Error>> {
+    // Clients 1 and 2 are in different shards
+    let csv_file = assert_fs::NamedTempFile::new("transactions.csv")?;
+    csv_file.write_str(
+        "type, client, tx, amount
+deposit, 1, 1, 5
+deposit, 2, 1, 3
+deposit, 2, 2, 4
+withdrawal, 1, 2, 1
+dispute, 2, 1,
+dispute, 1, 2,
+dispute, 1, 3,
+deposit, 1, 1, 5
+dispute, 1, 1,
+freeze, 2, 1,
+unlock, 1, 6,
+deposit, 2, 6, 1
+withdrawal, 1, 7, 100
+deposit, 2, 7, 1
+freeze, 3, 8,
+deposit, 2, 8, 1",
+    )?;
+
+    let mut outputs = Vec::new();
+    for shards in ["1", "4"] {
+        let rejections_file = assert_fs::NamedTempFile::new("rejections.csv")?;
+
+        let mut cmd = Command::cargo_bin(BIN_NAME)?;
+        cmd.arg(csv_file.path())
+            .arg("--shards")
+            .arg(shards)
+            .arg("--rejections")
+            .arg(rejections_file.path());
+        let stdout = cmd.assert().success().get_output().stdout.clone();
+
+        // Rejections from different shards may be written in any order
+        let rejections = std::fs::read_to_string(rejections_file.path())?;
+        let mut rows: Vec<String> = rejections.lines().skip(1).map(str::to_string).collect();
+        rows.sort();
+
+        outputs.push((String::from_utf8(stdout)?, rows));
+    }
+
+    // Ids of refused transactions can still be used by another client
+    assert_eq!(
+        "client,available,held,total,locked
+1,0,5,5,false
+2,6,0,6,false
+",
+        outputs[0].0
+    );
+
+    let mut reasons: Vec<&str> = outputs[0]
+        .1
+        .iter()
+        .filter_map(|row| row.split(',').nth(6))
+        .collect();
+    reasons.sort();
+    assert_eq!(
+        vec![
+            "client_mismatch",
+            "client_mismatch",
+            "duplicate_transaction_id",
+            "duplicate_transaction_id",
+            "duplicate_transaction_id",
+            "duplicate_transaction_id",
+            "insufficient_funds",
+            "unknown_account",
+            "unknown_transaction",
+        ],
+        reasons
+    );
+
+    assert_eq!(outputs[0], outputs[1]);
+
+    Ok(())
+}
+
+#[test]
+fn wal_recovers_ledger_between_runs() -> Result<(), Box<dyn std::error::Error>> {
+    let wal_file = assert_fs::NamedTempFile::new("ledger.wal")?;
+
+    let first_file = assert_fs::NamedTempFile::new("day1.csv")?;
+    first_file.write_str(
+        "type, client, tx, amount
+deposit, 1, 1, 2.5
+deposit, 2, 2, 1.5",
+    )?;
+
+    let second_file = assert_fs::NamedTempFile::new("day2.csv")?;
+    second_file.write_str(
+        "type, client, tx, amount
+dispute, 1, 1,
+withdrawal, 2, 3, 0.5",
+    )?;
+
+    let mut cmd = Command::cargo_bin(BIN_NAME)?;
+    cmd.arg(first_file.path()).arg("--wal").arg(wal_file.path());
+    cmd.assert().success();
+
+    // The dispute in the second file references a deposit from the first
+    let mut cmd = Command::cargo_bin(BIN_NAME)?;
+    cmd.arg(second_file.path())
+        .arg("--wal")
+        .arg(wal_file.path());
+    cmd.assert().success().stdout(
+        "client,available,held,total,locked
+1,0,2.5,2.5,false
+2,1,0,1,false
+",
+    );
+
+    Ok(())
+}
+
+#[test]
+fn wal_incomplete_entry_discarded() -> Result<(), Box<dyn std::error::Error>> {
+    let wal_file = assert_fs::NamedTempFile::new("ledger.wal")?;
+
+    let csv_file = assert_fs::NamedTempFile::new("transactions.csv")?;
+    csv_file.write_str(
+        "type, client, tx, amount
+deposit, 1, 1, 2.5",
+    )?;
+
+    let mut cmd = Command::cargo_bin(BIN_NAME)?;
+    cmd.arg(csv_file.path()).arg("--wal").arg(wal_file.path());
+    cmd.assert().success();
+
+    // Simulate the program being killed part way through writing an entry
+    let mut wal = std::fs::read_to_string(wal_file.path())?;
+    wal.push_str(r#"{"entry":"account","client_id":2,"#);
+    wal_file.write_str(&wal)?;
+
+    let empty_file = assert_fs::NamedTempFile::new("empty.csv")?;
+    empty_file.write_str("type, client, tx, amount")?;
+
+    let mut cmd = Command::cargo_bin(BIN_NAME)?;
+    cmd.arg(empty_file.path()).arg("--wal").arg(wal_file.path());
+    cmd.assert().success().stdout(
+        "client,available,held,total,locked
+1,2.5,0,2.5,false
+",
+    );
+
+    Ok(())
+}
+
+#[test]
+fn command_fails_when_wal_corrupt() -> Result<(), Box<dyn std::error::Error>> {
+    let wal_file = assert_fs::NamedTempFile::new("ledger.wal")?;
+    wal_file.write_str("not json\n")?;
+
+    let csv_file = assert_fs::NamedTempFile::new("transactions.csv")?;
+    csv_file.write_str("type, client, tx, amount")?;
+
+    let mut cmd = Command::cargo_bin(BIN_NAME)?;
+    cmd.arg(csv_file.path()).arg("--wal").arg(wal_file.path());
+
+    cmd.assert()
+        .failure()
+        .stderr(predicate::str::contains("Corrupt { line: 1"));
+
+    Ok(())
+}
+
+#[test]
+fn state_saved_and_loaded_between_runs() -> Result<(), Box<dyn std::error::Error>> {
+    let state_file = assert_fs::NamedTempFile::new("state.json")?;
+
+    let first_file = assert_fs::NamedTempFile::new("day1.csv")?;
+    first_file.write_str(
+        "type, client, tx, amount
+deposit, 1, 1, 2.5
+deposit, 1, 2, 1.5
+dispute, 1, 2,
+deposit, 2, 3, 1.25",
+    )?;
+
+    let second_file = assert_fs::NamedTempFile::new("day2.csv")?;
+    second_file.write_str(
+        "type, client, tx, amount
+chargeback, 1, 2,
+dispute, 2, 3,",
+    )?;
+
+    let mut cmd = Command::cargo_bin(BIN_NAME)?;
+    cmd.arg(first_file.path())
+        .arg("--save-state")
+        .arg(state_file.path());
+    cmd.assert().success();
+
+    state_file.assert(concat!(
+        r#"{"version":2,"accounts":["#,
+        r#"{"client_id":1,"available":"2.5","held":"1.5","is_locked":false,"is_closed":false},"#,
+        r#"{"client_id":2,"available":"1.25","held":"0","is_locked":false,"is_closed":false}],"#,
+        r#""transactions":["#,
+        r#"{"tx_type":"Deposit","client_id":1,"tx_id":1,"amount":"2.5","disputes":[]},"#,
+        r#"{"tx_type":"Deposit","client_id":1,"tx_id":2,"amount":"1.5","disputes":[{"dispute_id":null,"amount":"1.5","status":"Unresolved"}]},"#,
+        r#"{"tx_type":"Deposit","client_id":2,"tx_id":3,"amount":"1.25","disputes":[]}],"#,
+        r#""admin_transactions":[]}"#,
+        "\n"
+    ));
+
+    // The second file settles the dispute from the first, and the state is saved back to
+    // the same file
+    let mut cmd = Command::cargo_bin(BIN_NAME)?;
+    cmd.arg(second_file.path())
+        .arg("--load-state")
+        .arg(state_file.path())
+        .arg("--save-state")
+        .arg(state_file.path());
+    cmd.assert().success().stdout(
+        "client,available,held,total,locked
+1,2.5,0,2.5,true
+2,0,1.25,1.25,false
+",
+    );
+
+    let empty_file = assert_fs::NamedTempFile::new("empty.csv")?;
+    empty_file.write_str("type, client, tx, amount")?;
+
+    let mut cmd = Command::cargo_bin(BIN_NAME)?;
+    cmd.arg(empty_file.path())
+        .arg("--load-state")
+        .arg(state_file.path());
+    cmd.assert().success().stdout(
+        "client,available,held,total,locked
+1,2.5,0,2.5,true
+2,0,1.25,1.25,false
+",
+    );
+
+    Ok(())
+}
+
+#[test]
+fn command_fails_when_state_version_unsupported() -> Result<(), Box<dyn std::error::Error>> {
+    let state_file = assert_fs::NamedTempFile::new("state.json")?;
+    state_file.write_str(r#"{"version":3,"accounts":[],"transactions":[]}"#)?;
+
+    let csv_file = assert_fs::NamedTempFile::new("transactions.csv")?;
+    csv_file.write_str("type, client, tx, amount")?;
+
+    let mut cmd = Command::cargo_bin(BIN_NAME)?;
+    cmd.arg(csv_file.path())
+        .arg("--load-state")
+        .arg(state_file.path());
+
+    cmd.assert()
+        .failure()
+        .stderr(predicate::str::contains("UnsupportedVersion(3)"));
+
+    Ok(())
+}
+
+const COMPACT_STORAGE_CSV: &str = "type, client, tx, amount
+deposit, 1, 1, 2.5
+deposit, 2, 2, 1.5
+withdrawal, 1, 3, 1.25
+dispute, 1, 1, 0.5
+dispute, 2, 2,
+chargeback, 2, 2,
+dispute, 1, 3,
+deposit, 3, 1, 1.5
+withdrawal, 3, 3, 1.5";
+
+#[test]
+fn compact_storage_matches_hashmap_storage() -> Result<(), Box<dyn std::error::Error>> {
+    let csv_file = assert_fs::NamedTempFile::new("transactions.csv")?;
+    csv_file.write_str(COMPACT_STORAGE_CSV)?;
+
+    let expected_output = "client,available,held,total,locked
+1,0.75,1.75,2.5,false
+2,0,0,0,true
+";
+
+    let mut cmd = Command::cargo_bin(BIN_NAME)?;
+    cmd.arg(csv_file.path());
+    cmd.assert().success().stdout(expected_output);
+
+    let mut cmd = Command::cargo_bin(BIN_NAME)?;
+    cmd.arg(csv_file.path()).arg("--storage").arg("compact");
+    cmd.assert().success().stdout(expected_output);
+
+    let index_file = assert_fs::NamedTempFile::new("index.bin")?;
+    let mut cmd = Command::cargo_bin(BIN_NAME)?;
+    cmd.arg(csv_file.path())
+        .arg("--storage")
+        .arg("compact")
+        .arg("--index-file")
+        .arg(index_file.path());
+    cmd.assert().success().stdout(expected_output);
+
+    Ok(())
+}
+
+#[test]
+fn dropped_withdrawals_cannot_be_disputed() -> Result<(), Box<dyn std::error::Error>> {
+    let csv_file = assert_fs::NamedTempFile::new("transactions.csv")?;
+    csv_file.write_str(COMPACT_STORAGE_CSV)?;
+
+    let rejections_file = assert_fs::NamedTempFile::new("rejections.csv")?;
+
+    let mut cmd = Command::cargo_bin(BIN_NAME)?;
+    cmd.arg(csv_file.path())
+        .arg("--storage")
+        .arg("compact")
+        .arg("--drop-withdrawals")
+        .arg("--rejections")
+        .arg(rejections_file.path());
+
+    cmd.assert().success().stdout(
+        "client,available,held,total,locked
+1,0.75,0.5,1.25,false
+2,0,0,0,true
+",
+    );
+
+    // Duplicate ids are still detected for dropped withdrawals
+    let source = csv_file.path().display();
+    rejections_file.assert(format!(
+        "source,type,client,tx,amount,line,reason,message
+{source},dispute,1,3,,8,unknown_transaction,No transaction found with id: 3
+{source},deposit,3,1,1.5,9,duplicate_transaction_id,Duplicate transaction id: 1
+{source},withdrawal,3,3,1.5,10,duplicate_transaction_id,Duplicate transaction id: 3
+"
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn dropped_withdrawals_never_applied_twice() -> Result<(), Box<dyn std::error::Error>> {
+    let csv_file = assert_fs::NamedTempFile::new("transactions.csv")?;
+    csv_file.write_str(
+        "type, client, tx, amount
+deposit, 1, 1, 5
+withdrawal, 1, 2, 1
+withdrawal, 1, 2, 1
+deposit, 1, 2, 1
+withdrawal, 1, 200, 1
+withdrawal, 1, 200, 1",
+    )?;
+    let index_file = assert_fs::NamedTempFile::new("index.bin")?;
+
+    // Dropped withdrawals can't be told apart from conflicting transactions, so a re-delivered
+    // withdrawal is refused rather than debited again, whichever index is used
+    for use_index_file in [false, true] {
+        let mut cmd = Command::cargo_bin(BIN_NAME)?;
+        cmd.arg(csv_file.path())
+            .arg("--storage")
+            .arg("compact")
+            .arg("--drop-withdrawals");
+        if use_index_file {
+            cmd.arg("--index-file").arg(index_file.path());
+        }
+
+        cmd.assert().success().stdout(
+            "client,available,held,total,locked
+1,3,0,3,false
+",
+        );
+    }
+
+    Ok(())
+}
+
+#[test]
+fn command_fails_when_drop_withdrawals_without_compact_storage(
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = Command::cargo_bin(BIN_NAME)?;
+    cmd.arg("transactions.csv").arg("--drop-withdrawals");
+
+    cmd.assert().failure().stderr(predicate::str::contains(
+        "--index-file and --drop-withdrawals require --storage compact",
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn server_applies_transactions_from_multiple_connections() -> Result<(), Box<dyn std::error::Error>>
+{
+    let wal_file = assert_fs::NamedTempFile::new("ledger.wal")?;
+    let server = Server::start(&["--wal", wal_file.path().to_str().unwrap()])?;
+
+    let acks = server.send(
+        "type, client, tx, amount
+deposit, 1, 1, 2.5
+deposit, 2, 2, 1
+withdrawal, 2, 3, 5
+",
+    )?;
+    assert_eq!(
+        acks,
+        vec![
+            r#"{"line":2,"type":"deposit","client":"1","tx":"1","result":"accepted"}"#,
+            r#"{"line":3,"type":"deposit","client":"2","tx":"2","result":"accepted"}"#,
+            r#"{"line":4,"type":"withdrawal","client":"2","tx":"3","result":"rejected","reason":"insufficient_funds","message":"Insufficient funds available for client id 2 to process withdrawal 3"}"#,
+        ]
+    );
+
+    // The second connection shares the ledger, so it can dispute the first connection's deposit
+    let acks = server.send(
+        "type, client, tx, amount
+dispute, 1, 1,
+refund, 1, 4, 1
+",
+    )?;
+    assert_eq!(
+        acks,
+        vec![
+            r#"{"line":2,"type":"dispute","client":"1","tx":"1","result":"accepted"}"#,
+            r#"{"line":3,"type":"refund","client":"1","tx":"4","result":"invalid","reason":"unknown_type","message":"Invalid transaction: Unknown transaction type: refund"}"#,
+        ]
+    );
+
+    drop(server);
+
+    // Every change made by the server is recorded in the write-ahead log
+    let empty_file = assert_fs::NamedTempFile::new("empty.csv")?;
+    empty_file.write_str("type, client, tx, amount")?;
+
+    let mut cmd = Command::cargo_bin(BIN_NAME)?;
+    cmd.arg(empty_file.path()).arg("--wal").arg(wal_file.path());
+    cmd.assert().success().stdout(
+        "client,available,held,total,locked
+1,0,2.5,2.5,false
+2,1,0,1,false
+",
+    );
+
+    Ok(())
+}
+
+#[test]
+fn server_reads_jsonl_connections() -> Result<(), Box<dyn std::error::Error>> {
+    let server = Server::start(&["--input-format", "jsonl"])?;
+
+    let acks = server.send(
+        r#"{"type": "deposit", "client": 1, "tx": 1, "amount": "1.5"}
+{"type": "deposit", "client": 1, "tx": 1, "amount": "2.5"}
+{"type": "deposit", "client": 1, "tx": 1, "amount": "1.50"}
+"#,
+    )?;
+    assert_eq!(
+        acks,
+        vec![
+            r#"{"line":1,"type":"deposit","client":"1","tx":"1","result":"accepted"}"#,
+            r#"{"line":2,"type":"deposit","client":"1","tx":"1","result":"rejected","reason":"duplicate_transaction_id","message":"Duplicate transaction id: 1"}"#,
+            r#"{"line":3,"type":"deposit","client":"1","tx":"1","result":"replayed"}"#,
+        ]
+    );
+
+    Ok(())
+}
+
+#[test]
+fn http_api_submits_transactions_and_reads_balances() -> Result<(), Box<dyn std::error::Error>> {
+    let server = Server::start(&["--http", "127.0.0.1:0"])?;
+
+    // Transactions sent over TCP and HTTP are applied to the same ledger
+    server.send(
+        "type, client, tx, amount
+deposit, 1, 1, 2.5
+",
+    )?;
+
+    let response = server.request(
+        "POST",
+        "/transactions",
+        "application/json",
+        r#"{"type": "dispute", "client": 1, "tx": 1, "amount": "1"}
+{"type": "withdrawal", "client": 2, "tx": 2, "amount": "1"}"#,
+    )?;
+    assert_eq!(
+        response,
+        (
+            200,
+            r#"[{"line":1,"type":"dispute","client":"1","tx":"1","result":"accepted"},{"line":2,"type":"withdrawal","client":"2","tx":"2","result":"rejected","reason":"insufficient_funds","message":"Insufficient funds available for client id 2 to process withdrawal 2"}]
+"#
+            .to_string()
+        )
+    );
+
+    assert_eq!(
+        server.request("GET", "/accounts", "text/plain", "")?,
+        (
+            200,
+            r#"[{"client":1,"available":"1.5","held":"1","total":"2.5","locked":false}]
+"#
+            .to_string()
+        )
+    );
+
+    assert_eq!(
+        server.request("GET", "/accounts/1", "text/plain", "")?,
+        (
+            200,
+            r#"{"client":1,"available":"1.5","held":"1","total":"2.5","locked":false}
+"#
+            .to_string()
+        )
+    );
+
+    assert_eq!(
+        server.request("GET", "/transactions/1", "text/plain", "")?,
+        (
+            200,
+            r#"{"type":"deposit","client":1,"tx":1,"amount":"2.5","status":"disputed","disputes":[{"dispute":null,"amount":"1","status":"disputed"}]}
+"#
+            .to_string()
+        )
+    );
+
+    assert_eq!(
+        server.request("GET", "/accounts/3", "text/plain", "")?,
+        (
+            404,
+            r#"{"error":"unknown_account","message":"No account for client id 3"}
+"#
+            .to_string()
+        )
+    );
+
+    assert_eq!(
+        server.request("GET", "/transactions/2", "text/plain", "")?,
+        (
+            404,
+            r#"{"error":"unknown_transaction","message":"No deposit or withdrawal with transaction id 2"}
+"#
+            .to_string()
+        )
+    );
+
+    Ok(())
+}
+
+#[test]
+fn http_api_acknowledges_rows_before_unreadable_body() -> Result<(), Box<dyn std::error::Error>> {
+    let server = Server::start(&["--http", "127.0.0.1:0"])?;
+
+    // The third line isn't valid UTF-8, so nothing more can be read from the body
+    let mut body = br#"{"type": "deposit", "client": 1, "tx": 1, "amount": "2.5"}
+{"type": "withdrawal", "client": 1, "tx": 2, "amount": "5"}
+"#
+    .to_vec();
+    body.extend_from_slice(b"\xff\n");
+
+    let response = server.request("POST", "/transactions", "application/json", body)?;
+    assert_eq!(
+        response,
+        (
+            200,
+            r#"[{"line":1,"type":"deposit","client":"1","tx":"1","result":"accepted"},{"line":2,"type":"withdrawal","client":"1","tx":"2","result":"rejected","reason":"insufficient_funds","message":"Insufficient funds available for client id 1 to process withdrawal 2"},{"line":3,"type":"","client":"","tx":"","result":"failed","reason":"invalid_json","message":"JSON error: stream did not contain valid UTF-8"}]
+"#
+            .to_string()
+        )
+    );
+
+    // Rows before the error were still applied
+    assert_eq!(
+        server.request("GET", "/accounts/1", "text/plain", "")?,
+        (
+            200,
+            r#"{"client":1,"available":"2.5","held":"0","total":"2.5","locked":false}
+"#
+            .to_string()
+        )
+    );
+
+    Ok(())
+}
+
+#[test]
+fn http_api_returns_statements_when_recording_history() -> Result<(), Box<dyn std::error::Error>> {
+    let server = Server::start(&["--http", "127.0.0.1:0", "--history"])?;
+
+    server.send(
+        "type, client, tx, amount
+deposit, 1, 1, 2.5
+withdrawal, 1, 2, 3
+dispute, 1, 1,
+",
+    )?;
+
+    let (status, body) = server.request("GET", "/accounts/1/statement", "text/plain", "")?;
+    assert_eq!(status, 200);
+
+    let entries: Vec<serde_json::Value> = serde_json::from_str(&body)?;
+    let summary: Vec<_> = entries
+        .iter()
+        .map(|entry| {
+            format!(
+                "{},{},{},{},{}",
+                entry["type"], entry["result"], entry["reason"], entry["available"], entry["held"]
+            )
+        })
+        .collect();
+    assert_eq!(
+        summary,
+        [
+            r#""deposit","accepted",null,"2.5","0""#,
+            r#""withdrawal","rejected","insufficient_funds","2.5","0""#,
+            r#""dispute","accepted",null,"0","2.5""#,
+        ]
+    );
+
+    assert_eq!(
+        server.request("GET", "/accounts/2/statement", "text/plain", "")?,
+        (200, "[]\n".to_string())
+    );
+
+    Ok(())
+}
+
+#[test]
+fn http_api_statements_require_history() -> Result<(), Box<dyn std::error::Error>> {
+    let server = Server::start(&["--http", "127.0.0.1:0"])?;
+
+    assert_eq!(
+        server.request("GET", "/accounts/1/statement", "text/plain", "")?,
+        (
+            404,
+            r#"{"error":"history_disabled","message":"Statements are only available when the server is started with --history"}
+"#
+            .to_string()
+        )
+    );
+
+    Ok(())
+}
+
+#[test]
+fn command_fails_when_serve_used_with_shards() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = Command::cargo_bin(BIN_NAME)?;
+    cmd.arg("--shards").arg("2").arg("serve");
+
+    cmd.assert().failure().stderr(predicate::str::contains(
+        "serve can't be used with --shards, --save-state or --rejections",
+    ));
+
+    Ok(())
+}
+
+/// Ledger state used to check that refused transactions have no effect. Client 1 has an open
+/// dispute, client 2 is locked and client 3 is closed.
+const REJECTION_SETUP_CSV: &str = "type, client, tx, amount
+deposit, 1, 1, 10
+deposit, 1, 2, 5
+withdrawal, 1, 3, 2
+dispute, 1, 2,
+deposit, 2, 4, 3
+dispute, 2, 4,
+chargeback, 2, 4,
+deposit, 3, 5, 1
+withdrawal, 3, 6, 1
+close, 3, 7,";
+
+#[test]
+fn rejected_deposits_and_withdrawals_leave_ledger_unchanged(
+) -> Result<(), Box<dyn std::error::Error>> {
+    assert_rejected_without_effect(
+        REJECTION_SETUP_CSV,
+        "type, client, tx, amount
+deposit, 1, 1, 7
+withdrawal, 1, 2, 1
+deposit, 1, 10, -1
+withdrawal, 1, 11, 100
+withdrawal, 4, 12, 1
+deposit, 2, 13, 1
+deposit, 3, 14, 1
+deposit, 2, 4, 1",
+        &[
+            "duplicate_transaction_id",
+            "duplicate_transaction_id",
+            "non_positive_amount",
+            "insufficient_funds",
+            "insufficient_funds",
+            "account_locked",
+            "account_closed",
+            "duplicate_transaction_id",
+        ],
+    )
+}
+
+#[test]
+fn rejected_disputes_leave_ledger_unchanged() -> Result<(), Box<dyn std::error::Error>> {
+    assert_rejected_without_effect(
+        REJECTION_SETUP_CSV,
+        "type, client, tx, amount
+dispute, 1, 99,
+dispute, 4, 1,
+dispute, 1, 2, 1
+resolve, 1, 1,
+chargeback, 1, 1,
+dispute, 2, 4, 1
+dispute, 1, 1, 20
+dispute, 1, 1, -1
+dispute, 3, 5,",
+        &[
+            "unknown_transaction",
+            "client_mismatch",
+            "already_disputed",
+            "not_disputed",
+            "not_disputed",
+            "already_charged_back",
+            "dispute_exceeds_amount",
+            "non_positive_amount",
+            "account_closed",
+        ],
+    )
+}
+
+#[test]
+fn rejected_admin_transactions_leave_ledger_unchanged() -> Result<(), Box<dyn std::error::Error>> {
+    assert_rejected_without_effect(
+        REJECTION_SETUP_CSV,
+        "type, client, tx, amount, reason
+unlock, 4, 20, ,
+close, 1, 21, ,
+adjustment, 1, 22, 0, noop
+freeze, 3, 23, ,",
+        &[
+            "unknown_account",
+            "account_not_empty",
+            "zero_adjustment",
+            "account_closed",
+        ],
+    )
+}
+
+const REPLAY_CSV: &str = "type, client, tx, amount, dispute
+deposit, 1, 1, 10,
+deposit, 1, 2, 5,
+withdrawal, 1, 3, 2,
+dispute, 1, 1, 4, 1
+resolve, 1, 1, , 1
+dispute, 1, 2, , 2
+chargeback, 1, 2, , 2
+deposit, 2, 4, 3,
+dispute, 2, 4, ,";
+
+#[test]
+fn redelivered_transactions_replayed_without_effect() -> Result<(), Box<dyn std::error::Error>> {
+    assert_cmd_succeeds_with_result(REPLAY_CSV, &mut ["1,8,0,8,true", "2,0,3,3,false"])?;
+
+    // Every row is identical to one which has already been applied, so none are refused
+    assert_rejected_without_effect(REPLAY_CSV, REPLAY_CSV, &[])
+}
+
+#[test]
+fn redelivered_disputes_replayed_after_account_closed() -> Result<(), Box<dyn std::error::Error>> {
+    let setup_csv = "type, client, tx, amount, dispute
+deposit, 1, 1, 10,
+dispute, 1, 1, , 1
+resolve, 1, 1, , 1
+withdrawal, 1, 2, 10,
+close, 1, 3, ,";
+
+    // Re-delivered rows are recognised before the closed account is refused, but a new
+    // dispute is still refused
+    assert_rejected_without_effect(
+        setup_csv,
+        "type, client, tx, amount, dispute
+dispute, 1, 1, , 1
+resolve, 1, 1, , 1
+dispute, 1, 1, , 2",
+        &["account_closed"],
+    )
+}
+
+#[test]
+fn redelivered_admin_transactions_replayed_without_effect() -> Result<(), Box<dyn std::error::Error>>
+{
+    let csv_content = "type, client, tx, amount, reason
+deposit, 1, 1, 10,
+adjustment, 1, 2, 5, goodwill
+adjustment, 1, 2, 5, goodwill";
+
+    assert_cmd_succeeds_with_result(csv_content, &mut ["1,15,0,15,false"])?;
+
+    let setup_csv = "type, client, tx, amount, reason
+deposit, 1, 1, 10,
+adjustment, 1, 2, 5, goodwill
+freeze, 1, 3, ,";
+
+    assert_rejected_without_effect(setup_csv, setup_csv, &[])?;
+
+    // Administrative transactions with a recorded id but different details are refused
+    assert_rejected_without_effect(
+        setup_csv,
+        "type, client, tx, amount, reason
+adjustment, 1, 2, 6, goodwill
+adjustment, 1, 2, 5, duplicate_payout
+unlock, 1, 3, ,",
+        &[
+            "duplicate_transaction_id",
+            "duplicate_transaction_id",
+            "duplicate_transaction_id",
+        ],
+    )
+}
+
+#[test]
+fn admin_transaction_ids_conflict_with_recorded_transactions(
+) -> Result<(), Box<dyn std::error::Error>> {
+    let setup_csv = "type, client, tx, amount, reason
+deposit, 1, 1, 10,
+withdrawal, 1, 2, 5,
+adjustment, 1, 3, 1, goodwill";
+
+    // Ids are shared between all recorded transactions, whichever kind they are
+    assert_rejected_without_effect(
+        setup_csv,
+        "type, client, tx, amount, reason
+adjustment, 1, 1, 10, goodwill
+freeze, 1, 2, ,
+deposit, 1, 3, 1,
+withdrawal, 1, 3, 1,",
+        &[
+            "duplicate_transaction_id",
+            "duplicate_transaction_id",
+            "duplicate_transaction_id",
+            "duplicate_transaction_id",
+        ],
+    )
+}
+
+#[test]
+fn conflicting_duplicates_rejected() -> Result<(), Box<dyn std::error::Error>> {
+    assert_rejected_without_effect(
+        REPLAY_CSV,
+        "type, client, tx, amount, dispute
+deposit, 1, 1, 11,
+deposit, 2, 1, 10,
+withdrawal, 1, 1, 10,
+dispute, 1, 1, 3, 1
+dispute, 2, 4, 1,
+chargeback, 1, 1, , 1
+resolve, 1, 2, , 2",
+        &[
+            "duplicate_transaction_id",
+            "duplicate_transaction_id",
+            "duplicate_transaction_id",
+            "already_resolved",
+            "already_disputed",
+            "already_resolved",
+            "already_charged_back",
+        ],
+    )
+}
+
+#[test]
+fn dispute_without_id_replayed_after_resolve() -> Result<(), Box<dyn std::error::Error>> {
+    let setup_csv = "type, client, tx, amount
+deposit, 1, 1, 10
+dispute, 1, 1,
+resolve, 1, 1,";
+
+    // Rows without a dispute id all refer to the same dispute, so a re-delivered dispute
+    // doesn't hold the funds again once it's been resolved
+    assert_rejected_without_effect(
+        setup_csv,
+        "type, client, tx, amount
+dispute, 1, 1,
+chargeback, 1, 1,",
+        &["already_resolved"],
+    )?;
+
+    let csv_content = "type, client, tx, amount
+deposit, 1, 1, 10
+dispute, 1, 1,
+resolve, 1, 1,
+dispute, 1, 1,";
+
+    assert_cmd_succeeds_with_result(csv_content, &mut ["1,10,0,10,false"])
+}
+
+const STATEMENT_CSV: &str = "type, client, tx, amount
+deposit, 1, 1, 10
+deposit, 2, 2, 5
+withdrawal, 1, 3, 20
+dispute, 1, 1,
+withdrawal, 2, 4, 1.5
+resolve, 1, 1,
+deposit, 1, 1, 10
+withdrawal, 3, 5, 1
+chargeback, 1, 1,";
+
+#[test]
+fn statement_lists_transactions_with_running_balances() -> Result<(), Box<dyn std::error::Error>> {
+    let csv_file = assert_fs::NamedTempFile::new("transactions.csv")?;
+    csv_file.write_str(STATEMENT_CSV)?;
+
+    let mut cmd = Command::cargo_bin(BIN_NAME)?;
+    cmd.arg("statement").arg(csv_file.path());
+
+    // Clients are listed in order, each with their transactions in the order they were
+    // processed, including those which were refused
+    let source = csv_file.path().display();
+    cmd.assert().success().stdout(format!(
+        "client,source,line,type,tx,dispute,amount,reason_code,result,reason,available,held,total,locked,closed
+1,{source},2,deposit,1,,10,,accepted,,10,0,10,false,false
+1,{source},4,withdrawal,3,,20,,rejected,insufficient_funds,10,0,10,false,false
+1,{source},5,dispute,1,,,,accepted,,0,10,10,false,false
+1,{source},7,resolve,1,,,,accepted,,10,0,10,false,false
+1,{source},8,deposit,1,,10,,replayed,,10,0,10,false,false
+1,{source},10,chargeback,1,,,,rejected,already_resolved,10,0,10,false,false
+2,{source},3,deposit,2,,5,,accepted,,5,0,5,false,false
+2,{source},6,withdrawal,4,,1.5,,accepted,,3.5,0,3.5,false,false
+3,{source},9,withdrawal,5,,1,,rejected,insufficient_funds,0,0,0,false,false
+"
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn statement_written_for_selected_clients() -> Result<(), Box<dyn std::error::Error>> {
+    let csv_file = assert_fs::NamedTempFile::new("transactions.csv")?;
+    csv_file.write_str(STATEMENT_CSV)?;
+
+    let mut cmd = Command::cargo_bin(BIN_NAME)?;
+    cmd.arg("statement")
+        .arg("--client")
+        .arg("2")
+        .arg("--client")
+        .arg("4")
+        .arg("--output-format")
+        .arg("jsonl")
+        .arg("--output-scale")
+        .arg("2")
+        .arg(csv_file.path());
+
+    let source = serde_json::to_string(&csv_file.path())?;
+    cmd.assert().success().stdout(format!(
+        r#"{{"client":2,"source":{source},"line":3,"type":"deposit","tx":2,"dispute":null,"amount":"5.00","reason_code":null,"result":"accepted","reason":null,"available":"5.00","held":"0.00","total":"5.00","locked":false,"closed":false}}
+{{"client":2,"source":{source},"line":6,"type":"withdrawal","tx":4,"dispute":null,"amount":"1.50","reason_code":null,"result":"accepted","reason":null,"available":"3.50","held":"0.00","total":"3.50","locked":false,"closed":false}}
+"#
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn statement_shows_when_account_closed() -> Result<(), Box<dyn std::error::Error>> {
+    let csv_file = assert_fs::NamedTempFile::new("transactions.csv")?;
+    csv_file.write_str(
+        "type, client, tx, amount
+deposit, 1, 1, 1
+withdrawal, 1, 2, 1
+close, 1, 3,
+deposit, 1, 4, 1",
+    )?;
+
+    let mut cmd = Command::cargo_bin(BIN_NAME)?;
+    cmd.arg("statement").arg(csv_file.path());
+
+    let source = csv_file.path().display();
+    cmd.assert().success().stdout(format!(
+        "client,source,line,type,tx,dispute,amount,reason_code,result,reason,available,held,total,locked,closed
+1,{source},2,deposit,1,,1,,accepted,,1,0,1,false,false
+1,{source},3,withdrawal,2,,1,,accepted,,0,0,0,false,false
+1,{source},4,close,3,,,,accepted,,0,0,0,true,true
+1,{source},5,deposit,4,,1,,rejected,account_closed,0,0,0,true,true
+"
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn command_fails_when_statement_used_with_wal() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = Command::cargo_bin(BIN_NAME)?;
+    cmd.arg("statement")
+        .arg("--wal")
+        .arg("ledger.wal")
+        .arg("transactions.csv");
+
+    cmd.assert().failure().stderr(predicate::str::contains(
+        "statement can't be used with --shards, --wal, --save-state, --rejections or --audit-log",
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn audit_log_records_every_change_to_accounts() -> Result<(), Box<dyn std::error::Error>> {
+    let csv_file = assert_fs::NamedTempFile::new("transactions.csv")?;
+    csv_file.write_str(
+        "type, client, tx, amount, dispute
+deposit, 1, 1, 10,
+withdrawal, 1, 2, 20,
+withdrawal, 1, 2, 4,
+dispute, 1, 1, 5, 1
+deposit, 1, 1, 10,
+chargeback, 1, 1, , 1
+unlock, 1, 3, ,",
+    )?;
+
+    let audit_file = assert_fs::NamedTempFile::new("audit.jsonl")?;
+
+    let mut cmd = Command::cargo_bin(BIN_NAME)?;
+    cmd.arg(csv_file.path())
+        .arg("--audit-log")
+        .arg(audit_file.path());
+
+    cmd.assert().success().stdout(
+        "client,available,held,total,locked
+1,1,0,1,false
+",
+    );
+
+    // Refused and replayed transactions don't change the account, so have no events
+    audit_file.assert(
+        r#"{"seq":1,"event":"deposit","client":1,"tx":1,"dispute":null,"reason_code":null,"available_delta":"10","held_delta":"0","available":"10","held":"0","total":"10","locked":false,"closed":false}
+{"seq":2,"event":"withdrawal","client":1,"tx":2,"dispute":null,"reason_code":null,"available_delta":"-4","held_delta":"0","available":"6","held":"0","total":"6","locked":false,"closed":false}
+{"seq":3,"event":"dispute","client":1,"tx":1,"dispute":1,"reason_code":null,"available_delta":"-5","held_delta":"5","available":"1","held":"5","total":"6","locked":false,"closed":false}
+{"seq":4,"event":"chargeback","client":1,"tx":1,"dispute":1,"reason_code":null,"available_delta":"0","held_delta":"-5","available":"1","held":"0","total":"1","locked":true,"closed":false}
+{"seq":5,"event":"unlock","client":1,"tx":3,"dispute":null,"reason_code":null,"available_delta":"0","held_delta":"0","available":"1","held":"0","total":"1","locked":false,"closed":false}
+"#,
+    );
+
+    Ok(())
+}
+
+#[test]
+fn audit_log_continues_with_wal() -> Result<(), Box<dyn std::error::Error>> {
+    let wal_file = assert_fs::NamedTempFile::new("ledger.wal")?;
+    let audit_file = assert_fs::NamedTempFile::new("audit.jsonl")?;
+
+    for (tx, amount) in [(1, "2.5"), (2, "1")] {
+        let csv_file = assert_fs::NamedTempFile::new("transactions.csv")?;
+        csv_file.write_str(&format!(
+            "type, client, tx, amount
+deposit, 1, {tx}, {amount}"
+        ))?;
+
+        let mut cmd = Command::cargo_bin(BIN_NAME)?;
+        cmd.arg(csv_file.path())
+            .arg("--wal")
+            .arg(wal_file.path())
+            .arg("--audit-log")
+            .arg(audit_file.path());
+        cmd.assert().success();
+    }
+
+    audit_file.assert(
+        r#"{"seq":1,"event":"deposit","client":1,"tx":1,"dispute":null,"reason_code":null,"available_delta":"2.5","held_delta":"0","available":"2.5","held":"0","total":"2.5","locked":false,"closed":false}
+{"seq":2,"event":"deposit","client":1,"tx":2,"dispute":null,"reason_code":null,"available_delta":"1","held_delta":"0","available":"3.5","held":"0","total":"3.5","locked":false,"closed":false}
+"#,
+    );
+
+    // Without the WAL the ledger starts empty, so the existing events no longer describe it
+    let mut cmd = assert_cmd::Command::cargo_bin(BIN_NAME)?;
+    cmd.arg("-")
+        .arg("--audit-log")
+        .arg(audit_file.path())
+        .write_stdin("type, client, tx, amount\n");
+    cmd.assert()
+        .failure()
+        .stderr(predicate::str::contains("doesn't match the ledger"));
+
+    Ok(())
+}
+
+#[test]
+fn command_fails_when_audit_log_behind_wal() -> Result<(), Box<dyn std::error::Error>> {
+    let csv_file = assert_fs::NamedTempFile::new("transactions.csv")?;
+    csv_file.write_str(
+        "type, client, tx, amount
+deposit, 1, 1, 2.5
+deposit, 2, 2, 1
+withdrawal, 1, 3, 1",
+    )?;
+
+    let wal_file = assert_fs::NamedTempFile::new("ledger.wal")?;
+    let audit_file = assert_fs::NamedTempFile::new("audit.jsonl")?;
+
+    let mut cmd = Command::cargo_bin(BIN_NAME)?;
+    cmd.arg(csv_file.path())
+        .arg("--wal")
+        .arg(wal_file.path())
+        .arg("--audit-log")
+        .arg(audit_file.path());
+    cmd.assert().success();
+
+    // As if the program had been killed after committing the withdrawal to the WAL, but
+    // before writing its event
+    let events = std::fs::read_to_string(audit_file.path())?;
+    let events: Vec<&str> = events.lines().collect();
+    audit_file.write_str(&format!("{}\n", events[..events.len() - 1].join("\n")))?;
+
+    let mut cmd = assert_cmd::Command::cargo_bin(BIN_NAME)?;
+    cmd.arg("-")
+        .arg("--wal")
+        .arg(wal_file.path())
+        .arg("--audit-log")
+        .arg(audit_file.path())
+        .write_stdin("type, client, tx, amount\n");
+    cmd.assert().failure().stderr(predicate::str::contains(
+        "doesn't match the ledger for client id 1",
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn adjustment_reason_included_in_statement_and_audit_log() -> Result<(), Box<dyn std::error::Error>>
+{
+    let csv_file = assert_fs::NamedTempFile::new("transactions.csv")?;
+    csv_file.write_str(
+        "type, client, tx, amount, reason
+deposit, 1, 1, 2.5,
+adjustment, 1, 2, -1.5, duplicate_payout",
+    )?;
+
+    let mut cmd = Command::cargo_bin(BIN_NAME)?;
+    cmd.arg("statement").arg(csv_file.path());
+
+    let source = csv_file.path().display();
+    cmd.assert().success().stdout(format!(
+        "client,source,line,type,tx,dispute,amount,reason_code,result,reason,available,held,total,locked,closed
+1,{source},2,deposit,1,,2.5,,accepted,,2.5,0,2.5,false,false
+1,{source},3,adjustment,2,,-1.5,duplicate_payout,accepted,,1,0,1,false,false
+"
+    ));
+
+    let audit_file = assert_fs::NamedTempFile::new("audit.jsonl")?;
+
+    let mut cmd = Command::cargo_bin(BIN_NAME)?;
+    cmd.arg(csv_file.path())
+        .arg("--audit-log")
+        .arg(audit_file.path());
+    cmd.assert().success();
+
+    audit_file.assert(
+        r#"{"seq":1,"event":"deposit","client":1,"tx":1,"dispute":null,"reason_code":null,"available_delta":"2.5","held_delta":"0","available":"2.5","held":"0","total":"2.5","locked":false,"closed":false}
+{"seq":2,"event":"adjustment","client":1,"tx":2,"dispute":null,"reason_code":"duplicate_payout","available_delta":"-1.5","held_delta":"0","available":"1","held":"0","total":"1","locked":false,"closed":false}
+"#,
+    );
+
+    Ok(())
+}
+
+const AUDIT_CSV: &str = "type, client, tx, amount, dispute
+deposit, 1, 1, 10,
+deposit, 2, 2, 5,
+withdrawal, 1, 3, 4,
+dispute, 1, 3, ,
+chargeback, 1, 3, ,
+unlock, 1, 4, ,
+dispute, 2, 2, 2, 1
+withdrawal, 2, 5, 1,
+withdrawal, 2, 6, 5,
+freeze, 2, 7, ,
+deposit, 3, 8, 1,
+withdrawal, 3, 9, 1,
+close, 3, 10, ,";
+
+#[test]
+fn rebuild_from_audit_log_matches_every_storage_backend() -> Result<(), Box<dyn std::error::Error>>
+{
+    let csv_file = assert_fs::NamedTempFile::new("transactions.csv")?;
+    csv_file.write_str(AUDIT_CSV)?;
+
+    let mut cmd = Command::cargo_bin(BIN_NAME)?;
+    cmd.arg(csv_file.path());
+    let accounts = cmd.assert().success().get_output().stdout.clone();
+
+    let accounts_file = assert_fs::NamedTempFile::new("accounts.csv")?;
+    accounts_file.write_binary(&accounts)?;
+
+    // The accounts rebuilt from each backend's audit log match the default backend's output
+    for storage in ["hashmap", "compact"] {
+        let audit_file = assert_fs::NamedTempFile::new("audit.jsonl")?;
+
+        let mut cmd = Command::cargo_bin(BIN_NAME)?;
+        cmd.arg(csv_file.path())
+            .arg("--storage")
+            .arg(storage)
+            .arg("--audit-log")
+            .arg(audit_file.path());
+        cmd.assert().success();
+
+        let mut cmd = Command::cargo_bin(BIN_NAME)?;
+        cmd.arg("rebuild")
+            .arg(audit_file.path())
+            .arg("--verify")
+            .arg(accounts_file.path());
+        cmd.assert().success().stdout(accounts.clone());
+    }
+
+    Ok(())
+}
+
+#[test]
+fn rebuild_sorts_accounts() -> Result<(), Box<dyn std::error::Error>> {
+    let csv_file = assert_fs::NamedTempFile::new("transactions.csv")?;
+    csv_file.write_str(AUDIT_CSV)?;
+
+    let audit_file = assert_fs::NamedTempFile::new("audit.jsonl")?;
+
+    let mut cmd = Command::cargo_bin(BIN_NAME)?;
+    cmd.arg(csv_file.path())
+        .arg("--audit-log")
+        .arg(audit_file.path());
+    cmd.assert().success();
+
+    let mut cmd = Command::cargo_bin(BIN_NAME)?;
+    cmd.arg("rebuild")
+        .arg(audit_file.path())
+        .arg("--sort-by")
+        .arg("total");
+    cmd.assert().success().stdout(
+        "client,available,held,total,locked
+3,0,0,0,true
+2,2,2,4,true
+1,10,0,10,false
+",
+    );
+
+    Ok(())
+}
+
+#[test]
+fn rebuild_fails_when_accounts_differ() -> Result<(), Box<dyn std::error::Error>> {
+    let csv_file = assert_fs::NamedTempFile::new("transactions.csv")?;
+    csv_file.write_str(AUDIT_CSV)?;
+
+    let audit_file = assert_fs::NamedTempFile::new("audit.jsonl")?;
+
+    let mut cmd = Command::cargo_bin(BIN_NAME)?;
+    cmd.arg(csv_file.path())
+        .arg("--audit-log")
+        .arg(audit_file.path());
+    cmd.assert().success();
+
+    // Trailing zeros don't matter, but client 2's balances and client 4's account do
+    let accounts_file = assert_fs::NamedTempFile::new("accounts.csv")?;
+    accounts_file.write_str(
+        "client,available,held,total,locked
+1,10.00,0,10,false
+2,3,2,5,true
+3,0,0,0,true
+4,0,0,0,false
+",
+    )?;
+
+    let mut cmd = Command::cargo_bin(BIN_NAME)?;
+    cmd.arg("rebuild")
+        .arg(audit_file.path())
+        .arg("--verify")
+        .arg(accounts_file.path());
+
+    cmd.assert().failure().stderr(
+        "Account for client id 2 differs: expected available 3, held 2, total 5, locked true, \
+rebuilt available 2, held 2, total 4, locked true
+Account for client id 4 is expected, but has no events in the audit log
+Error: \"2 account(s) rebuilt from the audit log don't match "
+            .to_string()
+            + &accounts_file.path().display().to_string()
+            + "\"\n",
+    );
+
+    Ok(())
+}
+
+#[test]
+fn rebuild_fails_when_audit_log_incomplete() -> Result<(), Box<dyn std::error::Error>> {
+    let csv_file = assert_fs::NamedTempFile::new("transactions.csv")?;
+    csv_file.write_str(AUDIT_CSV)?;
+
+    let audit_file = assert_fs::NamedTempFile::new("audit.jsonl")?;
+
+    let mut cmd = Command::cargo_bin(BIN_NAME)?;
+    cmd.arg(csv_file.path())
+        .arg("--audit-log")
+        .arg(audit_file.path());
+    cmd.assert().success();
+
+    // Remove the second event, so the log no longer accounts for every change
+    let events = std::fs::read_to_string(audit_file.path())?;
+    let mut lines: Vec<&str> = events.lines().collect();
+    lines.remove(1);
+    audit_file.write_str(&(lines.join("\n") + "\n"))?;
+
+    let mut cmd = Command::cargo_bin(BIN_NAME)?;
+    cmd.arg("rebuild").arg(audit_file.path());
+
+    cmd.assert().failure().stderr(predicate::str::contains(
+        "OutOfSequence { line: 2, seq: 3 }",
+    ));
+
+    Ok(())
+}
+
+/// Checks that the transactions in `rejected_csv` which are refused have the expected reasons
+/// when processed after `setup_csv`, and that the accounts and recorded transactions are
+/// identical to processing the setup alone.
+fn assert_rejected_without_effect(
+    setup_csv: &str,
+    rejected_csv: &str,
+    expected_reasons: &[&str],
+) -> Result<(), Box<dyn std::error::Error>> {
+    let setup_file = assert_fs::NamedTempFile::new("setup.csv")?;
+    setup_file.write_str(setup_csv)?;
+
+    let rejected_file = assert_fs::NamedTempFile::new("rejected.csv")?;
+    rejected_file.write_str(rejected_csv)?;
+
+    let expected_state = assert_fs::NamedTempFile::new("expected.json")?;
+    let mut cmd = Command::cargo_bin(BIN_NAME)?;
+    cmd.arg(setup_file.path())
+        .arg("--save-state")
+        .arg(expected_state.path());
+    let expected_output = cmd.assert().success().get_output().stdout.clone();
+
+    let state = assert_fs::NamedTempFile::new("state.json")?;
+    let rejections_file = assert_fs::NamedTempFile::new("rejections.jsonl")?;
+    let mut cmd = Command::cargo_bin(BIN_NAME)?;
+    cmd.arg(setup_file.path())
+        .arg(rejected_file.path())
+        .arg("--save-state")
+        .arg(state.path())
+        .arg("--rejections")
+        .arg(rejections_file.path())
+        .arg("--rejections-format")
+        .arg("jsonl");
+    cmd.assert().success().stdout(expected_output);
+
+    let rejections = std::fs::read_to_string(rejections_file.path())?;
+    let reasons: Vec<String> = rejections
+        .lines()
+        .map(|line| {
+            let rejection: serde_json::Value = serde_json::from_str(line).unwrap();
+            rejection["reason"].as_str().unwrap().to_string()
+        })
+        .collect();
+    assert_eq!(expected_reasons, reasons.as_slice());
+
+    assert_eq!(
+        std::fs::read_to_string(expected_state.path())?,
+        std::fs::read_to_string(state.path())?
+    );
+
+    Ok(())
+}
+
+/// The `serve` subcommand running on a free port, which is killed when dropped
+struct Server {
+    process: std::process::Child,
+    addr: String,
+    /// Address of the HTTP API, if `--http` was given
+    http_addr: Option<String>,
+}
+
+impl Server {
+    fn start(args: &[&str]) -> Result<Server, Box<dyn std::error::Error>> {
+        let mut process = Command::cargo_bin(BIN_NAME)?
+            .arg("serve")
+            .arg("--listen")
+            .arg("127.0.0.1:0")
+            .args(args)
+            .stderr(std::process::Stdio::piped())
+            .spawn()?;
+
+        // The server reports the ports it's listening on once it's ready for connections
+        let mut stderr = std::io::BufReader::new(process.stderr.take().unwrap());
+        let mut read_addr = |prefix: &str| -> Result<String, Box<dyn std::error::Error>> {
+            let mut line = String::new();
+            std::io::BufRead::read_line(&mut stderr, &mut line)?;
+            match line.trim().strip_prefix(prefix) {
+                Some(addr) => Ok(addr.to_string()),
+                None => Err(format!("Unexpected server output: {}", line).into()),
+            }
+        };
+
+        let addr = read_addr("Listening on ");
+        let http_addr = if args.contains(&"--http") {
+            Some(read_addr("HTTP API listening on "))
+        } else {
+            None
+        };
+
+        match (addr, http_addr.transpose()) {
+            (Ok(addr), Ok(http_addr)) => Ok(Server {
+                process,
+                addr,
+                http_addr,
+            }),
+            (Err(err), _) | (_, Err(err)) => {
+                process.kill()?;
+                Err(err)
+            }
+        }
+    }
+
+    /// Sends the input on a new connection and returns the acknowledgements received
+    fn send(&self, input: &str) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+        use std::io::{Read, Write};
+
+        let mut stream = std::net::TcpStream::connect(&self.addr)?;
+        stream.write_all(input.as_bytes())?;
+        stream.shutdown(std::net::Shutdown::Write)?;
+
+        let mut acks = String::new();
+        stream.read_to_string(&mut acks)?;
+        Ok(acks.lines().map(str::to_string).collect())
+    }
+
+    /// Makes a request to the HTTP API and returns the response status and body
+    fn request(
+        &self,
+        method: &str,
+        path: &str,
+        content_type: &str,
+        body: impl AsRef<[u8]>,
+    ) -> Result<(u16, String), Box<dyn std::error::Error>> {
+        use std::io::{Read, Write};
+
+        let body = body.as_ref();
+        let mut stream = std::net::TcpStream::connect(self.http_addr.as_ref().unwrap())?;
+        write!(
+            stream,
+            "{} {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\nContent-Type: {}\r\nContent-Length: {}\r\n\r\n",
+            method,
+            path,
+            content_type,
+            body.len()
+        )?;
+        stream.write_all(body)?;
+
+        let mut response = String::new();
+        stream.read_to_string(&mut response)?;
+
+        let (head, body) = response
+            .split_once("\r\n\r\n")
+            .ok_or("Incomplete response")?;
+        let status = head.split(' ').nth(1).ok_or("Missing status")?.parse()?;
+        Ok((status, body.to_string()))
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.process.kill();
+        let _ = self.process.wait();
+    }
+}
+
 /// # Arguments
 ///
 /// * `csv_content` - Input to the program
 /// * `expected_rows` - Expected output rows, excluding header. Order is ignored.
+#[allow(clippy::assertions_on_constants)]
 fn assert_cmd_succeeds_with_result(
     csv_content: &str,
     expected_rows: &mut [&str],